@@ -0,0 +1,3674 @@
+use rustyline::{error::ReadlineError, DefaultEditor};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Debug, Display, Formatter},
+    fs::{read_to_string, write},
+    io::{stdin, stdout, IsTerminal, Write},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Debug)]
+pub enum Type {
+    Number(f64),
+    Int(i64),
+    String(String),
+    Bool(bool),
+    Variable(String),
+    Call(String, Span),
+    Instruction(Instruction, Span),
+    Block(Vec<Type>),
+    List(Vec<Type>),
+    Dict(HashMap<String, Type>),
+    Error(Error),
+    Nil,
+}
+
+impl Type {
+    fn get_number(&self) -> f64 {
+        match self {
+            Type::Number(n) => n.to_owned(),
+            Type::Int(i) => *i as f64,
+            _ => 0.0,
+        }
+    }
+
+    fn get_int(&self) -> i64 {
+        match self {
+            Type::Int(i) => i.to_owned(),
+            Type::Number(n) => *n as i64,
+            _ => 0,
+        }
+    }
+
+    fn get_string(&self) -> String {
+        match self {
+            Type::String(s) | Type::Variable(s) => s.to_owned(),
+            Type::Number(n) => n.to_string(),
+            Type::Int(i) => i.to_string(),
+            Type::Error(Error::Raised(message)) => message.to_owned(),
+            Type::Error(e) => format!("{e:?}").to_lowercase(),
+            _ => String::new(),
+        }
+    }
+
+    fn get_bool(&self) -> bool {
+        match self {
+            Type::Bool(n) => n.to_owned(),
+            _ => false,
+        }
+    }
+
+    fn get_block(&self) -> Vec<Type> {
+        match self {
+            Type::Block(b) | Type::List(b) => b.to_owned(),
+            Type::Nil => vec![],
+            other => vec![other.to_owned()],
+        }
+    }
+
+    fn get_list(&self) -> Vec<Type> {
+        match self {
+            Type::List(l) | Type::Block(l) => l.to_owned(),
+            Type::Nil => vec![],
+            other => vec![other.to_owned()],
+        }
+    }
+
+    /// Wraps the items in the same kind of collection as this one, so that
+    /// editing a list gives a list and editing a block gives a block.
+    fn same_kind(&self, items: Vec<Type>) -> Type {
+        match self {
+            Type::Block(_) => Type::Block(items),
+            _ => Type::List(items),
+        }
+    }
+
+    fn get_numbers(&self) -> Vec<f64> {
+        self.get_block().iter().map(|i| i.get_number()).collect()
+    }
+
+    fn get_matrix(&self) -> Vec<Vec<f64>> {
+        self.get_block()
+            .iter()
+            .map(|row| row.get_numbers())
+            .collect()
+    }
+
+    /// Compares by value; values of different types are never equal.
+    fn equals(&self, other: &Type) -> bool {
+        match (self, other) {
+            (Type::Number(a), Type::Number(b)) => a == b,
+            (Type::Int(a), Type::Int(b)) => a == b,
+            (Type::Int(_), Type::Number(_)) | (Type::Number(_), Type::Int(_)) => {
+                self.get_number() == other.get_number()
+            }
+            (Type::String(a), Type::String(b)) | (Type::Variable(a), Type::Variable(b)) => a == b,
+            (Type::Bool(a), Type::Bool(b)) => a == b,
+            (Type::Block(a), Type::Block(b)) | (Type::List(a), Type::List(b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.equals(b))
+            }
+            (Type::Instruction(a, _), Type::Instruction(b, _)) => a.name() == b.name(),
+            (Type::Call(a, _), Type::Call(b, _)) => a == b,
+            (Type::Error(_), Type::Error(_)) => self.get_string() == other.get_string(),
+            (Type::Dict(a), Type::Dict(b)) => {
+                a.len() == b.len()
+                    && a.iter()
+                        .all(|(k, v)| b.get(k).is_some_and(|other| v.equals(other)))
+            }
+            (Type::Nil, Type::Nil) => true,
+            _ => false,
+        }
+    }
+
+    fn type_name(&self) -> &str {
+        match self {
+            Type::Number(_) => "number",
+            Type::Int(_) => "int",
+            Type::String(_) => "string",
+            Type::Bool(_) => "bool",
+            Type::Variable(_) => "variable",
+            Type::Call(..) => "call",
+            Type::Instruction(..) => "instruction",
+            Type::Block(_) => "block",
+            Type::List(_) => "list",
+            Type::Dict(_) => "dict",
+            Type::Error(_) => "error",
+            Type::Nil => "nil",
+        }
+    }
+
+    fn to_source(&self) -> String {
+        match self {
+            Type::Number(n) if n.is_nan() => "nan".to_string(),
+            // Whole floats keep their point so they don't come back as integers
+            Type::Number(n) if n.is_finite() && n.fract() == 0.0 => format!("{n}.0"),
+            Type::Number(n) => n.to_string(),
+            Type::Int(i) => i.to_string(),
+            Type::String(s) => format!("\"{}\"", escape(s)),
+            Type::Bool(b) => b.to_string(),
+            Type::Variable(v) => format!("${v}"),
+            Type::Call(name, _) => name.to_owned(),
+            Type::Instruction(i, _) => i.name(),
+            Type::Block(b) => format!(
+                "{{ {} }}",
+                b.iter()
+                    .map(|i| i.to_source())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
+            Type::List(l) => format!("{} list", Type::Block(l.to_owned()).to_source()),
+            Type::Dict(d) => {
+                let mut keys: Vec<&String> = d.keys().collect();
+                keys.sort();
+                let mut source = "dict".to_string();
+                for key in keys {
+                    source += &format!(" \"{}\" {} set", escape(key), d[key].to_source());
+                }
+                source
+            }
+            Type::Error(e) => format!("\"{}\"", Type::Error(e.to_owned()).get_string()),
+            Type::Nil => "nil".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Error {
+    StackEmpty,
+    TypeMismatch,
+    WriteFailed,
+    UnknownLabel,
+    InvalidNumber,
+    FrozenVariable,
+    UndefinedVariable,
+    InvalidArgument,
+    DimensionMismatch,
+    NotFound,
+    IndexOutOfRange,
+    DivideByZero,
+    CyclicImport,
+    RequestFailed,
+    HttpStatus(u16),
+    Timeout,
+    Raised(String),
+    StackNotEmpty(String),
+    Parse(ParseError),
+    EndOfInput,
+    Interrupted,
+    Exit,
+    At(Span, Box<Error>),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Error::At(span, e) => write!(f, "{e} at {span}"),
+            Error::Parse(e) => write!(f, "{e}"),
+            Error::StackNotEmpty(items) => write!(f, "stack is not empty: {items}"),
+            e => write!(f, "{e:?}"),
+        }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ParseError::UnknownWord(word, span) => write!(f, "unknown word `{word}` at {span}"),
+            ParseError::UnterminatedBlock(span) => write!(f, "unterminated block at {span}"),
+            ParseError::UnterminatedString(span) => write!(f, "unterminated string at {span}"),
+            ParseError::UnterminatedElif(span) => {
+                write!(f, "elif without closing if-else at {span}")
+            }
+        }
+    }
+}
+
+impl Error {
+    /// The variant name without its payload, e.g. `HttpStatus`.
+    fn kind(&self) -> String {
+        let name = format!("{self:?}");
+        name.split('(').next().unwrap_or_default().to_string()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum ParseError {
+    UnknownWord(String, Span),
+    UnterminatedBlock(Span),
+    UnterminatedString(Span),
+    UnterminatedElif(Span),
+}
+
+/// Where a token starts in the source, counted from 1.
+#[derive(Clone, Copy)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    const START: Span = Span { line: 1, column: 1 };
+}
+
+impl Debug for Span {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Instruction {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Idiv,
+    Mod,
+    Pow,
+    Hypot,
+    Atan2,
+    DegToRad,
+    RadToDeg,
+    Lerp,
+    Sign,
+    Negate,
+    Floor,
+    Ceil,
+    Round,
+    Abs,
+    Sqrt,
+    Min,
+    Max,
+    IsPrime,
+    Primes,
+    Fib,
+    StepBy,
+    Concat,
+    Length,
+    Substring,
+    ReverseString,
+    CountChar,
+    IsDigit,
+    IsAlpha,
+    IsWhitespace,
+    ParseInt,
+    ParseFloat,
+    GroupDigits,
+    ToCsv,
+    FromCsv,
+    UrlEncode,
+    UrlDecode,
+    HttpGet,
+    Print,
+    Println,
+    Tee,
+    Table,
+    Input,
+    Prompt,
+    ReadNumber,
+    Equal,
+    NotEqual,
+    ApproxEqual,
+    LessThan,
+    GreaterThan,
+    LessThanEqual,
+    GreaterThanEqual,
+    Not,
+    And,
+    Or,
+    AssertType,
+    Eval,
+    WithTimeout,
+    Try,
+    TryFinally,
+    Catch,
+    Retry,
+    Raise,
+    When,
+    WhenNot,
+    Guard,
+    IfElse,
+    Cond,
+    Switch,
+    While,
+    WhileCount,
+    Until,
+    Let,
+    Def,
+    Freeze,
+    RenameVar,
+    WithMemory,
+    TraceVar,
+    VarHistory,
+    Swap,
+    Copy,
+    Dup,
+    Over,
+    Rot,
+    Pop,
+    Insert,
+    Change,
+    Delete,
+    Append,
+    List,
+    Push,
+    Get,
+    Len,
+    ListConcat,
+    ListInsert,
+    RemoveAt,
+    First,
+    Last,
+    Rest,
+    Dict,
+    Set,
+    GetKey,
+    Keys,
+    Fill,
+    Digits,
+    Undigits,
+    Partition,
+    GroupBy,
+    TakeWhile,
+    DropWhile,
+    ZipWith,
+    Scan,
+    Chunks,
+    Windows,
+    Transpose,
+    MatMul,
+    Dot,
+    Mean,
+    Median,
+    Stddev,
+    ListSum,
+    ListMax,
+    ListMin,
+    Enumerate,
+    Find,
+    JsonGet,
+    FindIndex,
+    All,
+    Any,
+    CountIf,
+    Analyze,
+    AssertEmpty,
+    Label,
+    Goto,
+    DumpState,
+    Import,
+    ImportAs,
+    Exit,
+}
+
+impl Instruction {
+    fn name(&self) -> String {
+        let mut name = String::new();
+        for c in format!("{self:?}").chars() {
+            if c.is_uppercase() && !name.is_empty() {
+                name.push('-');
+            }
+            name.push(c.to_ascii_lowercase());
+        }
+        name
+    }
+}
+
+#[derive(Clone)]
+pub struct Core {
+    pub stack: Vec<Type>,
+    pub memory: HashMap<String, Type>,
+    history: HashMap<String, Vec<Type>>,
+    frozen: HashSet<String>,
+    pub warn_leaks: bool,
+    pub epsilon: f64,
+    pub ring_stack: Option<usize>,
+    pub undefined_as_nil: bool,
+    pub include_paths: Vec<PathBuf>,
+    loading: HashSet<PathBuf>,
+    labels: Vec<HashMap<String, usize>>,
+    jump: Option<String>,
+    deadline: Option<Instant>,
+    position: Option<Span>,
+}
+
+/// Shows only what the program can see; interpreter settings and control
+/// state stay out of the `Result :` line.
+impl Debug for Core {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Core")
+            .field("stack", &self.stack)
+            .field("memory", &self.memory)
+            .finish()
+    }
+}
+
+impl Default for Core {
+    fn default() -> Self {
+        Core::new()
+    }
+}
+
+impl Core {
+    pub fn new() -> Core {
+        Core {
+            stack: vec![],
+            memory: HashMap::from([
+                ("new-line".to_string(), Type::String("\n".to_string())),
+                ("double-quote".to_string(), Type::String("\"".to_string())),
+                ("tab".to_string(), Type::String("\t".to_string())),
+            ]),
+            history: HashMap::new(),
+            frozen: HashSet::new(),
+            warn_leaks: false,
+            epsilon: 0.0,
+            ring_stack: None,
+            undefined_as_nil: false,
+            include_paths: vec![],
+            loading: HashSet::new(),
+            labels: vec![],
+            jump: None,
+            deadline: None,
+            position: None,
+        }
+    }
+
+    /// Parses the source, rejecting words that are neither instructions nor
+    /// procedures defined with `def` somewhere in the same source.
+    pub fn parse(source: String) -> Result<Vec<Type>, ParseError> {
+        let program = Core::parse_words(source, Span::START)?;
+        let mut known = HashSet::new();
+        defined_procedures(&program, &mut known);
+        check_calls(&program, &known)?;
+        Ok(program)
+    }
+
+    /// Like `parse`, but also accepts procedures already defined in this interpreter,
+    /// so that the REPL can call procedures from earlier entries, and those defined
+    /// by modules the source imports.
+    pub fn compile(&self, source: String) -> Result<Vec<Type>, ParseError> {
+        let program = Core::parse_words(source, Span::START)?;
+        let mut known: HashSet<String> = self
+            .memory
+            .iter()
+            .filter(|(_, value)| matches!(value, Type::Block(_)))
+            .map(|(name, _)| name.to_owned())
+            .collect();
+        defined_procedures(&program, &mut known);
+        self.imported_procedures(&program, &mut known, &mut HashSet::new());
+        check_calls(&program, &known)?;
+        Ok(program)
+    }
+
+    fn imported_procedures(
+        &self,
+        code: &[Type],
+        names: &mut HashSet<String>,
+        seen: &mut HashSet<PathBuf>,
+    ) {
+        for (index, order) in code.iter().enumerate() {
+            match (order, code.get(index + 1), code.get(index + 2)) {
+                (Type::String(path), Some(Type::Instruction(Instruction::Import, _)), _) => {
+                    names.extend(self.module_procedures(path, seen));
+                }
+                (
+                    Type::String(path),
+                    Some(Type::String(prefix)),
+                    Some(Type::Instruction(Instruction::ImportAs, _)),
+                ) => {
+                    let module = self.module_procedures(path, seen);
+                    names.extend(module.iter().map(|name| format!("{prefix}.{name}")));
+                }
+                (Type::Block(block), _, _) => self.imported_procedures(block, names, seen),
+                _ => {}
+            }
+        }
+    }
+
+    /// The procedures a module defines, including those of modules it imports.
+    /// `seen` holds the modules being read, so that cyclic imports stop.
+    fn module_procedures(&self, path: &str, seen: &mut HashSet<PathBuf>) -> HashSet<String> {
+        let mut names = HashSet::new();
+        // Broken modules are left for `import` itself to report
+        let Ok(path) = self.resolve_import(path) else {
+            return names;
+        };
+        if !seen.insert(path.clone()) {
+            return names;
+        }
+        if let Ok(code) = read_to_string(&path) {
+            if let Ok(module) = Core::parse_words(code, Span::START) {
+                defined_procedures(&module, &mut names);
+                self.imported_procedures(&module, &mut names, seen);
+            }
+        }
+        seen.remove(&path);
+        names
+    }
+
+    fn parse_words(source: String, start: Span) -> Result<Vec<Type>, ParseError> {
+        fn tokenize_expr(input: String, start: Span) -> Result<Vec<(String, Span)>, ParseError> {
+            let mut tokens = Vec::new();
+            let mut current_token = String::new();
+            let mut in_parentheses: usize = 0;
+            let mut in_quote = false;
+            let mut in_comment = false;
+            let mut escaped = false;
+            let mut position = start;
+            let mut token_start = start;
+
+            for c in input.chars() {
+                // A token starts at the first character pushed to it
+                if current_token.is_empty() {
+                    token_start = position;
+                }
+                if c == '\n' {
+                    position.line += 1;
+                    position.column = 1;
+                } else {
+                    position.column += 1;
+                }
+                if in_comment {
+                    if c != '\n' {
+                        continue;
+                    }
+                    in_comment = false;
+                }
+                if escaped {
+                    escaped = false;
+                    current_token.push(c);
+                    continue;
+                }
+                match c {
+                    '{' if !in_quote => {
+                        in_parentheses += 1;
+                        current_token.push(c);
+                    }
+                    '}' if !in_quote => {
+                        if in_parentheses != 0 {
+                            current_token.push(c);
+                            in_parentheses -= 1;
+                            if in_parentheses == 0 {
+                                tokens.push((current_token.clone(), token_start));
+                                current_token.clear();
+                            }
+                        }
+                    }
+                    '"' if in_parentheses == 0 => {
+                        if in_quote {
+                            current_token.push(c);
+                            in_quote = false;
+                            tokens.push((current_token.clone(), token_start));
+                            current_token.clear();
+                        } else {
+                            in_quote = true;
+                            current_token.push(c);
+                        }
+                    }
+                    '\\' if in_parentheses == 0 && in_quote => {
+                        escaped = true;
+                        current_token.push(c);
+                    }
+                    '#' if in_parentheses == 0 && !in_quote => {
+                        in_comment = true;
+                        if !current_token.is_empty() {
+                            tokens.push((current_token.clone(), token_start));
+                            current_token.clear();
+                        }
+                    }
+                    ' ' | '　' | '\n' | '\t' | '\r' => {
+                        if in_parentheses != 0 || in_quote {
+                            current_token.push(c);
+                        } else if !current_token.is_empty() {
+                            tokens.push((current_token.clone(), token_start));
+                            current_token.clear();
+                        }
+                    }
+                    _ => {
+                        current_token.push(c);
+                    }
+                }
+            }
+
+            // A quote inside an open block belongs to the block
+            if in_parentheses != 0 {
+                return Err(ParseError::UnterminatedBlock(token_start));
+            }
+            if in_quote {
+                return Err(ParseError::UnterminatedString(token_start));
+            }
+            if !current_token.is_empty() {
+                tokens.push((current_token, token_start));
+            }
+            Ok(tokens)
+        }
+        Core::parse_tokens(tokenize_expr(source, start)?)
+    }
+
+    fn parse_tokens(tokens: Vec<(String, Span)>) -> Result<Vec<Type>, ParseError> {
+        let mut result = vec![];
+        let mut tokens = tokens.into_iter();
+        while let Some((token, span)) = tokens.next() {
+            let mut token = token.trim().to_string();
+            let special = match token.as_str() {
+                "inf" => Some(f64::INFINITY),
+                "-inf" => Some(f64::NEG_INFINITY),
+                "nan" => Some(f64::NAN),
+                _ => None,
+            };
+            if let Some(n) = special {
+                result.push(Type::Number(n));
+            } else if is_number_literal(&token) {
+                // Literals without a fraction or exponent are integers, unless they overflow
+                match token.parse::<i64>() {
+                    Ok(i) => result.push(Type::Int(i)),
+                    Err(_) => result.push(Type::Number(token.parse().unwrap_or_default())),
+                }
+            } else if token.starts_with('"') && token.ends_with('"') {
+                token.remove(token.find('"').unwrap_or_default());
+                token.remove(token.rfind('"').unwrap_or_default());
+                result.push(Type::String(unescape(&token)));
+            } else if token.starts_with("{") && token.ends_with("}") {
+                token.remove(token.find('{').unwrap_or_default());
+                token.remove(token.rfind('}').unwrap_or_default());
+                // The block's contents start just after its opening brace
+                let inner = Span {
+                    column: span.column + 1,
+                    ..span
+                };
+                result.push(Type::Block(Core::parse_words(token, inner)?));
+            } else if token.starts_with("$") {
+                token.remove(token.find('$').unwrap_or_default());
+                result.push(Type::Variable(token));
+            } else {
+                match token.as_str() {
+                    // `a {A} elif b {B} {C} if-else` is sugar for
+                    // `a {A} { b {B} {C} if-else } if-else`, so everything up to
+                    // the closing `if-else` becomes the else branch
+                    "elif" => {
+                        let mut rest = vec![];
+                        let mut closed = false;
+                        for (token, span) in tokens.by_ref() {
+                            closed = token.trim() == "if-else";
+                            rest.push((token, span));
+                            if closed {
+                                break;
+                            }
+                        }
+                        if !closed {
+                            return Err(ParseError::UnterminatedElif(span));
+                        }
+                        result.push(Type::Block(Core::parse_tokens(rest)?));
+                        result.push(Type::Instruction(Instruction::IfElse, span));
+                    }
+                    "true" => result.push(Type::Bool(true)),
+                    "false" => result.push(Type::Bool(false)),
+                    "nil" => result.push(Type::Nil),
+                    "add" => result.push(Type::Instruction(Instruction::Add, span)),
+                    "sub" => result.push(Type::Instruction(Instruction::Sub, span)),
+                    "mul" => result.push(Type::Instruction(Instruction::Mul, span)),
+                    "div" => result.push(Type::Instruction(Instruction::Div, span)),
+                    "idiv" => result.push(Type::Instruction(Instruction::Idiv, span)),
+                    "mod" => result.push(Type::Instruction(Instruction::Mod, span)),
+                    "pow" => result.push(Type::Instruction(Instruction::Pow, span)),
+                    "hypot" => result.push(Type::Instruction(Instruction::Hypot, span)),
+                    "atan2" => result.push(Type::Instruction(Instruction::Atan2, span)),
+                    "deg-to-rad" => result.push(Type::Instruction(Instruction::DegToRad, span)),
+                    "rad-to-deg" => result.push(Type::Instruction(Instruction::RadToDeg, span)),
+                    "lerp" => result.push(Type::Instruction(Instruction::Lerp, span)),
+                    "sign" => result.push(Type::Instruction(Instruction::Sign, span)),
+                    "negate" => result.push(Type::Instruction(Instruction::Negate, span)),
+                    "floor" => result.push(Type::Instruction(Instruction::Floor, span)),
+                    "ceil" => result.push(Type::Instruction(Instruction::Ceil, span)),
+                    "round" => result.push(Type::Instruction(Instruction::Round, span)),
+                    "abs" => result.push(Type::Instruction(Instruction::Abs, span)),
+                    "sqrt" => result.push(Type::Instruction(Instruction::Sqrt, span)),
+                    "min" => result.push(Type::Instruction(Instruction::Min, span)),
+                    "max" => result.push(Type::Instruction(Instruction::Max, span)),
+                    "is-prime" => result.push(Type::Instruction(Instruction::IsPrime, span)),
+                    "primes" => result.push(Type::Instruction(Instruction::Primes, span)),
+                    "fib" => result.push(Type::Instruction(Instruction::Fib, span)),
+                    "step-by" => result.push(Type::Instruction(Instruction::StepBy, span)),
+                    "concat" => result.push(Type::Instruction(Instruction::Concat, span)),
+                    "length" => result.push(Type::Instruction(Instruction::Length, span)),
+                    "substring" => result.push(Type::Instruction(Instruction::Substring, span)),
+                    "reverse-string" => {
+                        result.push(Type::Instruction(Instruction::ReverseString, span))
+                    }
+                    "count-char" => result.push(Type::Instruction(Instruction::CountChar, span)),
+                    "is-digit" => result.push(Type::Instruction(Instruction::IsDigit, span)),
+                    "is-alpha" => result.push(Type::Instruction(Instruction::IsAlpha, span)),
+                    "is-whitespace" => {
+                        result.push(Type::Instruction(Instruction::IsWhitespace, span))
+                    }
+                    "parse-int" => result.push(Type::Instruction(Instruction::ParseInt, span)),
+                    "parse-float" => result.push(Type::Instruction(Instruction::ParseFloat, span)),
+                    "group-digits" => {
+                        result.push(Type::Instruction(Instruction::GroupDigits, span))
+                    }
+                    "to-csv" => result.push(Type::Instruction(Instruction::ToCsv, span)),
+                    "from-csv" => result.push(Type::Instruction(Instruction::FromCsv, span)),
+                    "url-encode" => result.push(Type::Instruction(Instruction::UrlEncode, span)),
+                    "url-decode" => result.push(Type::Instruction(Instruction::UrlDecode, span)),
+                    "http-get" => result.push(Type::Instruction(Instruction::HttpGet, span)),
+                    "print" => result.push(Type::Instruction(Instruction::Print, span)),
+                    "println" => result.push(Type::Instruction(Instruction::Println, span)),
+                    "tee" => result.push(Type::Instruction(Instruction::Tee, span)),
+                    "table" => result.push(Type::Instruction(Instruction::Table, span)),
+                    "input" => result.push(Type::Instruction(Instruction::Input, span)),
+                    "prompt" => result.push(Type::Instruction(Instruction::Prompt, span)),
+                    "read-number" => result.push(Type::Instruction(Instruction::ReadNumber, span)),
+                    "equal" => result.push(Type::Instruction(Instruction::Equal, span)),
+                    "not-equal" => result.push(Type::Instruction(Instruction::NotEqual, span)),
+                    "approx-equal" => {
+                        result.push(Type::Instruction(Instruction::ApproxEqual, span))
+                    }
+                    "less-than" => result.push(Type::Instruction(Instruction::LessThan, span)),
+                    "greater-than" => {
+                        result.push(Type::Instruction(Instruction::GreaterThan, span))
+                    }
+                    "less-than-equal" => {
+                        result.push(Type::Instruction(Instruction::LessThanEqual, span))
+                    }
+                    "greater-than-equal" => {
+                        result.push(Type::Instruction(Instruction::GreaterThanEqual, span))
+                    }
+                    "not" => result.push(Type::Instruction(Instruction::Not, span)),
+                    "and" => result.push(Type::Instruction(Instruction::And, span)),
+                    "or" => result.push(Type::Instruction(Instruction::Or, span)),
+                    "assert-type" => result.push(Type::Instruction(Instruction::AssertType, span)),
+                    "eval" => result.push(Type::Instruction(Instruction::Eval, span)),
+                    "with-timeout" => {
+                        result.push(Type::Instruction(Instruction::WithTimeout, span))
+                    }
+                    "try" => result.push(Type::Instruction(Instruction::Try, span)),
+                    "try-finally" => result.push(Type::Instruction(Instruction::TryFinally, span)),
+                    "catch" => result.push(Type::Instruction(Instruction::Catch, span)),
+                    "retry" => result.push(Type::Instruction(Instruction::Retry, span)),
+                    "raise" => result.push(Type::Instruction(Instruction::Raise, span)),
+                    "when" => result.push(Type::Instruction(Instruction::When, span)),
+                    "when-not" => result.push(Type::Instruction(Instruction::WhenNot, span)),
+                    "guard" => result.push(Type::Instruction(Instruction::Guard, span)),
+                    "if-else" => result.push(Type::Instruction(Instruction::IfElse, span)),
+                    "cond" => result.push(Type::Instruction(Instruction::Cond, span)),
+                    "switch" => result.push(Type::Instruction(Instruction::Switch, span)),
+                    "while" => result.push(Type::Instruction(Instruction::While, span)),
+                    "while-count" => result.push(Type::Instruction(Instruction::WhileCount, span)),
+                    "until" => result.push(Type::Instruction(Instruction::Until, span)),
+                    "let" => result.push(Type::Instruction(Instruction::Let, span)),
+                    "def" => result.push(Type::Instruction(Instruction::Def, span)),
+                    "freeze" => result.push(Type::Instruction(Instruction::Freeze, span)),
+                    "rename-var" => result.push(Type::Instruction(Instruction::RenameVar, span)),
+                    "with-memory" => result.push(Type::Instruction(Instruction::WithMemory, span)),
+                    "trace-var" => result.push(Type::Instruction(Instruction::TraceVar, span)),
+                    "var-history" => result.push(Type::Instruction(Instruction::VarHistory, span)),
+                    "swap" => result.push(Type::Instruction(Instruction::Swap, span)),
+                    "copy" => result.push(Type::Instruction(Instruction::Copy, span)),
+                    "dup" => result.push(Type::Instruction(Instruction::Dup, span)),
+                    "over" => result.push(Type::Instruction(Instruction::Over, span)),
+                    "rot" => result.push(Type::Instruction(Instruction::Rot, span)),
+                    "pop" => result.push(Type::Instruction(Instruction::Pop, span)),
+                    "insert" => result.push(Type::Instruction(Instruction::Insert, span)),
+                    "change" => result.push(Type::Instruction(Instruction::Change, span)),
+                    "delete" => result.push(Type::Instruction(Instruction::Delete, span)),
+                    "append" => result.push(Type::Instruction(Instruction::Append, span)),
+                    "list" => result.push(Type::Instruction(Instruction::List, span)),
+                    "push" => result.push(Type::Instruction(Instruction::Push, span)),
+                    "get" => result.push(Type::Instruction(Instruction::Get, span)),
+                    "len" => result.push(Type::Instruction(Instruction::Len, span)),
+                    "list-concat" => result.push(Type::Instruction(Instruction::ListConcat, span)),
+                    "list-insert" => result.push(Type::Instruction(Instruction::ListInsert, span)),
+                    "remove-at" => result.push(Type::Instruction(Instruction::RemoveAt, span)),
+                    "first" => result.push(Type::Instruction(Instruction::First, span)),
+                    "last" => result.push(Type::Instruction(Instruction::Last, span)),
+                    "rest" => result.push(Type::Instruction(Instruction::Rest, span)),
+                    "dict" => result.push(Type::Instruction(Instruction::Dict, span)),
+                    "set" => result.push(Type::Instruction(Instruction::Set, span)),
+                    "get-key" => result.push(Type::Instruction(Instruction::GetKey, span)),
+                    "keys" => result.push(Type::Instruction(Instruction::Keys, span)),
+                    "fill" => result.push(Type::Instruction(Instruction::Fill, span)),
+                    "digits" => result.push(Type::Instruction(Instruction::Digits, span)),
+                    "undigits" => result.push(Type::Instruction(Instruction::Undigits, span)),
+                    "partition" => result.push(Type::Instruction(Instruction::Partition, span)),
+                    "group-by" => result.push(Type::Instruction(Instruction::GroupBy, span)),
+                    "take-while" => result.push(Type::Instruction(Instruction::TakeWhile, span)),
+                    "drop-while" => result.push(Type::Instruction(Instruction::DropWhile, span)),
+                    "zip-with" => result.push(Type::Instruction(Instruction::ZipWith, span)),
+                    "scan" => result.push(Type::Instruction(Instruction::Scan, span)),
+                    "chunks" => result.push(Type::Instruction(Instruction::Chunks, span)),
+                    "windows" => result.push(Type::Instruction(Instruction::Windows, span)),
+                    "transpose" => result.push(Type::Instruction(Instruction::Transpose, span)),
+                    "mat-mul" => result.push(Type::Instruction(Instruction::MatMul, span)),
+                    "dot" => result.push(Type::Instruction(Instruction::Dot, span)),
+                    "mean" => result.push(Type::Instruction(Instruction::Mean, span)),
+                    "median" => result.push(Type::Instruction(Instruction::Median, span)),
+                    "stddev" => result.push(Type::Instruction(Instruction::Stddev, span)),
+                    "list-sum" => result.push(Type::Instruction(Instruction::ListSum, span)),
+                    "list-max" => result.push(Type::Instruction(Instruction::ListMax, span)),
+                    "list-min" => result.push(Type::Instruction(Instruction::ListMin, span)),
+                    "enumerate" => result.push(Type::Instruction(Instruction::Enumerate, span)),
+                    "find" => result.push(Type::Instruction(Instruction::Find, span)),
+                    "json-get" => result.push(Type::Instruction(Instruction::JsonGet, span)),
+                    "find-index" => result.push(Type::Instruction(Instruction::FindIndex, span)),
+                    "all" => result.push(Type::Instruction(Instruction::All, span)),
+                    "any" => result.push(Type::Instruction(Instruction::Any, span)),
+                    "count-if" => result.push(Type::Instruction(Instruction::CountIf, span)),
+                    "analyze" => result.push(Type::Instruction(Instruction::Analyze, span)),
+                    "assert-empty" => {
+                        result.push(Type::Instruction(Instruction::AssertEmpty, span))
+                    }
+                    "label" => result.push(Type::Instruction(Instruction::Label, span)),
+                    "goto" => result.push(Type::Instruction(Instruction::Goto, span)),
+                    "dump-state" => result.push(Type::Instruction(Instruction::DumpState, span)),
+                    "import" => result.push(Type::Instruction(Instruction::Import, span)),
+                    "import-as" => result.push(Type::Instruction(Instruction::ImportAs, span)),
+                    "exit" => result.push(Type::Instruction(Instruction::Exit, span)),
+                    // Any other word calls a procedure defined by `def`
+                    _ => result.push(Type::Call(token, span)),
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Removes `#` line comments from the source, leaving string literals and
+    /// everything else untouched, so that tools can work on the bare code.
+    pub fn strip_comments(source: &str) -> String {
+        let mut result = String::new();
+        let mut in_quote = false;
+        let mut in_comment = false;
+        let mut escaped = false;
+
+        for c in source.chars() {
+            if in_comment {
+                if c != '\n' {
+                    continue;
+                }
+                in_comment = false;
+            }
+            if escaped {
+                escaped = false;
+            } else {
+                match c {
+                    '\\' if in_quote => escaped = true,
+                    '"' => in_quote = !in_quote,
+                    '#' if !in_quote => {
+                        in_comment = true;
+                        continue;
+                    }
+                    _ => {}
+                }
+            }
+            result.push(c);
+        }
+        result
+    }
+
+    pub fn eval(&mut self, program: Vec<Type>) -> Result<(), Error> {
+        let mut labels = HashMap::new();
+        for (index, pair) in program.windows(2).enumerate() {
+            if let [Type::String(name), Type::Instruction(Instruction::Label, _)] = pair {
+                labels.insert(name.to_owned(), index + 2);
+            }
+        }
+        if self.labels.is_empty() {
+            self.position = None;
+        }
+        self.labels.push(labels);
+        let result = self.run(program);
+        self.labels.pop();
+
+        // Only the outermost program reports where it failed, so that `catch`
+        // and friends still see the bare error
+        match (result, self.position) {
+            (Err(e), Some(span)) if self.labels.is_empty() && !matches!(e, Error::Exit) => {
+                Err(Error::At(span, Box::new(e)))
+            }
+            (result, _) => result,
+        }
+    }
+
+    fn run(&mut self, program: Vec<Type>) -> Result<(), Error> {
+        let mut index = 0;
+        loop {
+            // Checked before every step, as a running block can't be interrupted
+            if self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                return Err(Error::Timeout);
+            }
+            if let Some(name) = self.jump.take() {
+                match self.labels.last().and_then(|labels| labels.get(&name)) {
+                    Some(target) => index = *target,
+                    None => {
+                        self.jump = Some(name);
+                        return Ok(());
+                    }
+                }
+            }
+            let Some(order) = program.get(index).cloned() else {
+                return Ok(());
+            };
+            index += 1;
+
+            if let Type::Instruction(_, span) | Type::Call(_, span) = &order {
+                self.position = Some(*span);
+            }
+            match order {
+                Type::Instruction(instruction, _) => match instruction {
+                    Instruction::Add => {
+                        let b = self.pop()?;
+                        let a = self.pop()?;
+                        self.push(arithmetic(a, b, i64::checked_add, |a, b| a + b))
+                    }
+                    Instruction::Sub => {
+                        let b = self.pop()?;
+                        let a = self.pop()?;
+                        self.push(arithmetic(a, b, i64::checked_sub, |a, b| a - b))
+                    }
+                    Instruction::Mul => {
+                        let b = self.pop()?;
+                        let a = self.pop()?;
+                        self.push(arithmetic(a, b, i64::checked_mul, |a, b| a * b))
+                    }
+                    Instruction::Div => {
+                        let b = self.pop()?.get_number();
+                        let a = self.pop()?.get_number();
+                        if b == 0.0 {
+                            return Err(Error::DivideByZero);
+                        }
+                        self.push(Type::Number(a / b))
+                    }
+                    Instruction::Idiv => {
+                        let b = self.pop()?.get_int();
+                        let a = self.pop()?.get_int();
+                        if b == 0 {
+                            return Err(Error::DivideByZero);
+                        }
+                        self.push(Type::Int(a.checked_div(b).ok_or(Error::InvalidArgument)?))
+                    }
+                    Instruction::Mod => {
+                        let b = self.pop()?;
+                        let a = self.pop()?;
+                        if b.get_number() == 0.0 {
+                            return Err(Error::DivideByZero);
+                        }
+                        self.push(arithmetic(a, b, i64::checked_rem, |a, b| a % b))
+                    }
+                    Instruction::Pow => {
+                        let b = self.pop()?.get_number();
+                        let a = self.pop()?.get_number();
+                        self.push(Type::Number(a.powf(b)))
+                    }
+                    Instruction::Hypot => {
+                        let b = self.pop()?.get_number();
+                        let a = self.pop()?.get_number();
+                        self.push(Type::Number(a.hypot(b)))
+                    }
+                    Instruction::Atan2 => {
+                        // Operands are pushed as `y x`
+                        let x = self.pop()?.get_number();
+                        let y = self.pop()?.get_number();
+                        self.push(Type::Number(y.atan2(x)))
+                    }
+                    Instruction::DegToRad => {
+                        let a = self.pop()?.get_number();
+                        self.push(Type::Number(a.to_radians()))
+                    }
+                    Instruction::RadToDeg => {
+                        let a = self.pop()?.get_number();
+                        self.push(Type::Number(a.to_degrees()))
+                    }
+                    Instruction::Lerp => {
+                        // Operands are pushed as `t end start`
+                        let start = self.pop()?.get_number();
+                        let end = self.pop()?.get_number();
+                        let t = self.pop()?.get_number();
+                        self.push(Type::Number(start + (end - start) * t))
+                    }
+                    Instruction::Sign => match self.pop()? {
+                        Type::Int(n) => self.push(Type::Int(n.signum())),
+                        other => {
+                            // NaN has no sign, so it stays NaN
+                            let n = other.get_number();
+                            let sign = if n > 0.0 {
+                                1.0
+                            } else if n < 0.0 {
+                                -1.0
+                            } else if n == 0.0 {
+                                0.0
+                            } else {
+                                f64::NAN
+                            };
+                            self.push(Type::Number(sign));
+                        }
+                    },
+                    Instruction::Negate => {
+                        // Subtracting from zero keeps integers and never gives -0
+                        let a = self.pop()?;
+                        self.push(arithmetic(Type::Int(0), a, i64::checked_sub, |a, b| a - b));
+                    }
+                    // Rounding an integer leaves it as it is
+                    Instruction::Floor => match self.pop()? {
+                        Type::Int(n) => self.push(Type::Int(n)),
+                        other => self.push(Type::Number(other.get_number().floor())),
+                    },
+                    Instruction::Ceil => match self.pop()? {
+                        Type::Int(n) => self.push(Type::Int(n)),
+                        other => self.push(Type::Number(other.get_number().ceil())),
+                    },
+                    Instruction::Round => match self.pop()? {
+                        // Halfway cases round away from zero, so 2.5 is 3 and -2.5 is -3
+                        Type::Int(n) => self.push(Type::Int(n)),
+                        other => self.push(Type::Number(other.get_number().round())),
+                    },
+                    Instruction::Abs => match self.pop()? {
+                        Type::Int(n) if n != i64::MIN => self.push(Type::Int(n.abs())),
+                        other => self.push(Type::Number(other.get_number().abs())),
+                    },
+                    Instruction::Sqrt => {
+                        // Negative numbers give NaN, like other out-of-domain math
+                        let n = self.pop()?.get_number();
+                        self.push(Type::Number(n.sqrt()));
+                    }
+                    // A NaN operand is ignored in favour of the other one
+                    Instruction::Min => {
+                        let b = self.pop()?.get_number();
+                        let a = self.pop()?.get_number();
+                        self.push(Type::Number(a.min(b)));
+                    }
+                    Instruction::Max => {
+                        let b = self.pop()?.get_number();
+                        let a = self.pop()?.get_number();
+                        self.push(Type::Number(a.max(b)));
+                    }
+                    Instruction::IsPrime => {
+                        // Infinity and NaN aren't integers, so they aren't prime
+                        let prime = match self.pop()? {
+                            Type::Int(n) => n >= 2 && is_prime(n as u64),
+                            other => {
+                                let n = other.get_number().trunc();
+                                n.is_finite() && n >= 2.0 && is_prime(n as u64)
+                            }
+                        };
+                        self.push(Type::Bool(prime));
+                    }
+                    Instruction::Primes => {
+                        // The sieve is as long as the limit, so it gets the same bound
+                        let limit = collection_size(self.pop()?.get_number().max(0.0))?;
+                        let mut sieve = vec![true; limit + 1];
+                        let mut primes = vec![];
+                        for n in 2..=limit {
+                            if sieve[n] {
+                                primes.push(Type::Int(n as i64));
+                                for multiple in (n * n..=limit).step_by(n) {
+                                    sieve[multiple] = false;
+                                }
+                            }
+                        }
+                        self.push(Type::List(primes));
+                    }
+                    Instruction::Fib => {
+                        // 0 fib is 0 and 1 fib is 1. Integers give an exact Int up to
+                        // 92 fib; past that results are floats and become inf from
+                        // 1477 fib onwards
+                        match self.pop()? {
+                            Type::Int(n @ 0..=92) => {
+                                let (mut a, mut b) = (0i64, 1i64);
+                                for _ in 0..n {
+                                    (a, b) = (b, a.saturating_add(b));
+                                }
+                                self.push(Type::Int(a));
+                            }
+                            other => {
+                                let n = other.get_number();
+                                if n.is_nan() || n < 0.0 {
+                                    return Err(Error::InvalidArgument);
+                                }
+                                let (mut a, mut b) = (0.0, 1.0);
+                                for _ in 0..n as usize {
+                                    // Once inf, every later result is inf too
+                                    if a == f64::INFINITY {
+                                        break;
+                                    }
+                                    (a, b) = (b, a + b);
+                                }
+                                self.push(Type::Number(a));
+                            }
+                        }
+                    }
+                    Instruction::StepBy => {
+                        let count = self.pop()?.get_number();
+                        let amount = self.pop()?.get_number();
+                        let start = self.pop()?.get_number();
+                        let sequence = (0..collection_size(count.max(0.0))?)
+                            .map(|i| Type::Number(start + amount * i as f64))
+                            .collect();
+                        self.push(Type::List(sequence));
+                    }
+                    Instruction::Concat => {
+                        let b = self.pop()?.get_string();
+                        let a = self.pop()?.get_string();
+                        self.push(Type::String(a + &b));
+                    }
+                    Instruction::Length => {
+                        let a = self.pop()?.get_string();
+                        self.push(Type::Int(a.chars().count() as i64));
+                    }
+                    Instruction::Substring => {
+                        // Indexes count characters and are clamped to the string
+                        let end = self.pop()?.get_number().max(0.0) as usize;
+                        let start = self.pop()?.get_number().max(0.0) as usize;
+                        let a = self.pop()?.get_string();
+                        let slice = a.chars().skip(start).take(end.saturating_sub(start));
+                        self.push(Type::String(slice.collect()));
+                    }
+                    Instruction::ReverseString => {
+                        // Reverses by character, so combining marks end up detached
+                        let a = self.pop()?.get_string();
+                        self.push(Type::String(a.chars().rev().collect()));
+                    }
+                    Instruction::CountChar => {
+                        // The needle must be exactly one character
+                        let needle = self.pop()?.get_string();
+                        let haystack = self.pop()?.get_string();
+                        let mut chars = needle.chars();
+                        let (Some(needle), None) = (chars.next(), chars.next()) else {
+                            return Err(Error::InvalidArgument);
+                        };
+                        let count = haystack.chars().filter(|c| *c == needle).count();
+                        self.push(Type::Int(count as i64));
+                    }
+                    // Character predicates only look at the first character
+                    Instruction::IsDigit => {
+                        let c = self.pop()?.get_string().chars().next();
+                        self.push(Type::Bool(c.is_some_and(|c| c.is_ascii_digit())));
+                    }
+                    Instruction::IsAlpha => {
+                        let c = self.pop()?.get_string().chars().next();
+                        self.push(Type::Bool(c.is_some_and(|c| c.is_alphabetic())));
+                    }
+                    Instruction::IsWhitespace => {
+                        let c = self.pop()?.get_string().chars().next();
+                        self.push(Type::Bool(c.is_some_and(|c| c.is_whitespace())));
+                    }
+                    Instruction::ParseInt => {
+                        let text = self.pop()?.get_string();
+                        let result = match text.trim().parse::<i64>() {
+                            Ok(n) => vec![Type::Int(n), Type::Bool(true)],
+                            Err(_) => vec![Type::Error(Error::InvalidNumber), Type::Bool(false)],
+                        };
+                        self.push(Type::List(result));
+                    }
+                    Instruction::ParseFloat => {
+                        let text = self.pop()?.get_string();
+                        let result = match text.trim().parse::<f64>() {
+                            Ok(n) => vec![Type::Number(n), Type::Bool(true)],
+                            Err(_) => vec![Type::Error(Error::InvalidNumber), Type::Bool(false)],
+                        };
+                        self.push(Type::List(result));
+                    }
+                    Instruction::GroupDigits => {
+                        let (separator, number) = match self.pop()? {
+                            Type::String(s) => (s, self.pop()?),
+                            other => (",".to_string(), other),
+                        };
+                        self.push(Type::String(group_digits(&number, &separator)));
+                    }
+                    Instruction::ToCsv => {
+                        let rows: Vec<Vec<String>> = self
+                            .pop()?
+                            .get_list()
+                            .iter()
+                            .map(|row| row.get_list().iter().map(|i| i.get_string()).collect())
+                            .collect();
+                        self.push(Type::String(to_csv(&rows)));
+                    }
+                    Instruction::FromCsv => {
+                        let text = self.pop()?.get_string();
+                        let rows = from_csv(&text).ok_or(Error::InvalidArgument)?;
+                        let rows = rows
+                            .into_iter()
+                            .map(|row| Type::List(row.into_iter().map(Type::String).collect()))
+                            .collect();
+                        self.push(Type::List(rows));
+                    }
+                    Instruction::UrlEncode => {
+                        let text = self.pop()?.get_string();
+                        self.push(Type::String(url_encode(&text)));
+                    }
+                    Instruction::UrlDecode => {
+                        let text = self.pop()?.get_string();
+                        let decoded = url_decode(&text).ok_or(Error::InvalidArgument)?;
+                        self.push(Type::String(decoded));
+                    }
+                    Instruction::HttpGet => {
+                        let url = self.pop()?.get_string();
+                        self.push(http_get(&url));
+                    }
+                    Instruction::Print => {
+                        let a = self.pop()?.get_string();
+                        print!("{}", a);
+                    }
+                    Instruction::Println => {
+                        let a = self.pop()?.get_string();
+                        println!("{}", a);
+                    }
+                    Instruction::Tee => {
+                        let a = self.pop()?;
+                        eprintln!("{}", a.to_source());
+                        self.push(a);
+                    }
+                    Instruction::Table => {
+                        let rows: Vec<Vec<String>> = self
+                            .pop()?
+                            .get_block()
+                            .iter()
+                            .map(|row| row.get_block().iter().map(|i| i.get_string()).collect())
+                            .collect();
+                        print!("{}", format_table(&rows));
+                    }
+                    Instruction::Input => self.push(Type::String(read_line("")?)),
+                    Instruction::Prompt => {
+                        let prompt = self.pop()?.get_string();
+                        self.push(Type::String(read_line(&prompt)?));
+                    }
+                    Instruction::ReadNumber => {
+                        let line = read_line("")?;
+                        let line = line.trim();
+                        if let Ok(n) = line.parse::<i64>() {
+                            self.push(Type::Int(n));
+                        } else {
+                            let n = line.parse::<f64>().map_err(|_| Error::InvalidNumber)?;
+                            self.push(Type::Number(n));
+                        }
+                    }
+                    Instruction::Equal => {
+                        let b = self.pop()?;
+                        let a = self.pop()?;
+                        self.push(Type::Bool(self.is_equal(&a, &b)));
+                    }
+                    Instruction::NotEqual => {
+                        let b = self.pop()?;
+                        let a = self.pop()?;
+                        self.push(Type::Bool(!self.is_equal(&a, &b)));
+                    }
+                    Instruction::ApproxEqual => {
+                        let tolerance = self.pop()?.get_number();
+                        let b = self.pop()?.get_number();
+                        let a = self.pop()?.get_number();
+                        self.push(Type::Bool((a - b).abs() <= tolerance))
+                    }
+                    Instruction::LessThan => {
+                        let b = self.pop()?.get_number();
+                        let a = self.pop()?.get_number();
+                        self.push(Type::Bool(a < b))
+                    }
+                    Instruction::GreaterThan => {
+                        let b = self.pop()?.get_number();
+                        let a = self.pop()?.get_number();
+                        self.push(Type::Bool(a > b))
+                    }
+                    Instruction::LessThanEqual => {
+                        let b = self.pop()?.get_number();
+                        let a = self.pop()?.get_number();
+                        self.push(Type::Bool(a <= b))
+                    }
+                    Instruction::GreaterThanEqual => {
+                        let b = self.pop()?.get_number();
+                        let a = self.pop()?.get_number();
+                        self.push(Type::Bool(a >= b))
+                    }
+                    Instruction::Not => {
+                        let a = self.pop()?.get_bool();
+                        self.push(Type::Bool(!a))
+                    }
+                    Instruction::And => {
+                        let b = self.pop()?.get_bool();
+                        let a = self.pop()?.get_bool();
+                        self.push(Type::Bool(a && b))
+                    }
+                    Instruction::Or => {
+                        let b = self.pop()?.get_bool();
+                        let a = self.pop()?.get_bool();
+                        self.push(Type::Bool(a || b))
+                    }
+                    Instruction::AssertType => {
+                        let name = self.pop()?.get_string();
+                        let value = self.pop()?;
+                        // Integers still pass as numbers
+                        let is_number = name == "number" && matches!(value, Type::Int(_));
+                        if value.type_name() != name && !is_number {
+                            return Err(Error::TypeMismatch);
+                        }
+                        self.push(value);
+                    }
+                    Instruction::Eval => {
+                        let code = self.pop()?.get_block();
+                        self.eval(code)?;
+                    }
+                    Instruction::WithTimeout => {
+                        let budget = self.pop()?.get_number();
+                        let code = self.pop()?.get_block();
+                        let budget = Duration::try_from_secs_f64(budget / 1000.0)
+                            .map_err(|_| Error::InvalidArgument)?;
+                        // An inner budget can't outlive the one around it
+                        let saved = self.deadline;
+                        self.deadline = match Instant::now().checked_add(budget) {
+                            Some(deadline) => Some(saved.map_or(deadline, |s| s.min(deadline))),
+                            None => saved,
+                        };
+                        let result = self.eval(code);
+                        self.deadline = saved;
+                        result?;
+                    }
+                    Instruction::Try | Instruction::TryFinally => {
+                        // try-finally takes a third block that runs after the others
+                        // whether or not they failed
+                        let cleanup = match instruction {
+                            Instruction::TryFinally => Some(self.pop()?.get_block()),
+                            _ => None,
+                        };
+                        let handler = self.pop()?.get_block();
+                        let code = self.pop()?.get_block();
+                        let saved = self.stack.clone();
+                        let result = match self.eval(code) {
+                            // `exit` isn't a failure, so it isn't handled
+                            Err(e) if !matches!(e, Error::Exit) => {
+                                self.stack = saved;
+                                self.push(Type::String(Type::Error(e).get_string()));
+                                self.eval(handler)
+                            }
+                            ok => ok,
+                        };
+                        if let Some(cleanup) = cleanup {
+                            // A pending goto would skip the cleanup, so it waits until after
+                            let jump = self.jump.take();
+                            self.eval(cleanup)?;
+                            if jump.is_some() {
+                                self.jump = jump;
+                            }
+                        }
+                        result?;
+                    }
+                    Instruction::Catch => {
+                        let kind = self.pop()?.get_string();
+                        let handler = self.pop()?.get_block();
+                        let code = self.pop()?.get_block();
+                        let saved = self.stack.clone();
+                        match self.eval(code) {
+                            Err(e) if e.kind() == kind => {
+                                // The handler starts from the stack as it was before
+                                // the block, with the error on top
+                                self.stack = saved;
+                                self.push(Type::String(Type::Error(e).get_string()));
+                                self.eval(handler)?;
+                            }
+                            result => result?,
+                        }
+                    }
+                    Instruction::Retry => {
+                        let attempts = self.pop()?.get_number();
+                        let code = self.pop()?.get_block();
+                        if attempts < 1.0 {
+                            return Err(Error::InvalidArgument);
+                        }
+                        // Every attempt starts from the same stack
+                        let saved = self.stack.clone();
+                        let mut last = Ok(());
+                        for _ in 0..attempts as usize {
+                            self.stack = saved.clone();
+                            last = self.eval(code.clone());
+                            if matches!(last, Ok(()) | Err(Error::Exit)) {
+                                break;
+                            }
+                        }
+                        match last {
+                            Err(Error::Exit) => return last,
+                            Err(e) => {
+                                self.stack = saved;
+                                self.push(Type::Error(e));
+                            }
+                            Ok(()) => {}
+                        }
+                    }
+                    Instruction::Raise => {
+                        let message = self.pop()?.get_string();
+                        return Err(Error::Raised(message));
+                    }
+                    Instruction::When => {
+                        let code = self.pop()?.get_block();
+                        let condition = self.pop()?.get_bool();
+                        if condition {
+                            self.eval(code)?;
+                        };
+                    }
+                    Instruction::WhenNot => {
+                        let code = self.pop()?.get_block();
+                        let condition = self.pop()?.get_bool();
+                        if !condition {
+                            self.eval(code)?;
+                        };
+                    }
+                    Instruction::Guard => {
+                        if !self.pop()?.get_bool() {
+                            return Ok(());
+                        }
+                    }
+                    Instruction::IfElse => {
+                        let code_false = self.pop()?.get_block();
+                        let code_true = self.pop()?.get_block();
+                        let condition = self.pop()?.get_bool();
+                        if condition {
+                            self.eval(code_true)?;
+                        } else {
+                            self.eval(code_false)?;
+                        };
+                    }
+                    Instruction::Cond => {
+                        // Clauses alternate between condition and body blocks
+                        let clauses = self.pop()?.get_block();
+                        for clause in clauses.chunks(2) {
+                            self.eval(clause[0].get_block())?;
+                            if self.pop()?.get_bool() {
+                                if let Some(body) = clause.get(1) {
+                                    self.eval(body.get_block())?;
+                                }
+                                break;
+                            }
+                        }
+                    }
+                    Instruction::Switch => {
+                        let index = self.pop()?.get_number();
+                        let default = self.pop()?.get_block();
+                        let branches = self.pop()?.get_block();
+                        // Out of range indexes run the default block
+                        let branch = match branches.get(index as usize) {
+                            Some(branch) if index >= 0.0 => branch.get_block(),
+                            _ => default,
+                        };
+                        self.eval(branch)?;
+                    }
+                    Instruction::While => {
+                        let code = self.pop()?.get_block();
+                        let condition = self.pop()?.get_block();
+                        let mut warned = false;
+                        while self.jump.is_none() && {
+                            self.eval(condition.clone())?;
+                            self.pop()?.get_bool()
+                        } {
+                            let depth = self.stack.len();
+                            self.eval(code.clone())?;
+                            self.warn_leak(depth, &mut warned);
+                        }
+                    }
+                    Instruction::WhileCount => {
+                        let code = self.pop()?.get_block();
+                        let condition = self.pop()?.get_block();
+                        let mut warned = false;
+                        let mut count = 0;
+                        while self.jump.is_none() && {
+                            self.eval(condition.clone())?;
+                            self.pop()?.get_bool()
+                        } {
+                            let depth = self.stack.len();
+                            self.push(Type::Int(count));
+                            self.eval(code.clone())?;
+                            self.warn_leak(depth, &mut warned);
+                            count += 1;
+                        }
+                    }
+                    Instruction::Until => {
+                        let code = self.pop()?.get_block();
+                        let condition = self.pop()?.get_block();
+                        let mut warned = false;
+                        while self.jump.is_none() && {
+                            self.eval(condition.clone())?;
+                            !self.pop()?.get_bool()
+                        } {
+                            let depth = self.stack.len();
+                            self.eval(code.clone())?;
+                            self.warn_leak(depth, &mut warned);
+                        }
+                    }
+                    Instruction::Let => {
+                        let name = self.pop()?.get_string();
+                        let value = self.pop()?;
+                        if self.frozen.contains(&name) {
+                            return Err(Error::FrozenVariable);
+                        }
+                        if let Some(history) = self.history.get_mut(&name) {
+                            history.push(value.clone());
+                        }
+                        self.memory.insert(name, value);
+                    }
+                    Instruction::Def => {
+                        let name = self.pop()?.get_string();
+                        let Type::Block(code) = self.pop()? else {
+                            return Err(Error::TypeMismatch);
+                        };
+                        if self.frozen.contains(&name) {
+                            return Err(Error::FrozenVariable);
+                        }
+                        self.memory.insert(name, Type::Block(code));
+                    }
+                    Instruction::Freeze => {
+                        let name = self.pop()?.get_string();
+                        self.frozen.insert(name);
+                    }
+                    Instruction::RenameVar => {
+                        let new = self.pop()?.get_string();
+                        let old = self.pop()?.get_string();
+                        // An unbound old name is UndefinedVariable; frozen names can't move
+                        if self.frozen.contains(&old) || self.frozen.contains(&new) {
+                            return Err(Error::FrozenVariable);
+                        }
+                        let value = self.memory.remove(&old).ok_or(Error::UndefinedVariable)?;
+                        self.memory.insert(new, value);
+                    }
+                    Instruction::WithMemory => {
+                        let code = self.pop()?.get_block();
+                        let Type::Dict(bindings) = self.pop()? else {
+                            return Err(Error::TypeMismatch);
+                        };
+                        // Frozen names can't be shadowed either
+                        if bindings.iter().any(|(name, _)| self.frozen.contains(name)) {
+                            return Err(Error::FrozenVariable);
+                        }
+                        let mut saved = vec![];
+                        for (name, value) in bindings {
+                            saved.push((name.clone(), self.memory.insert(name, value)));
+                        }
+                        let result = self.eval(code);
+                        for (name, value) in saved.into_iter().rev() {
+                            match value {
+                                Some(value) => self.memory.insert(name, value),
+                                None => self.memory.remove(&name),
+                            };
+                        }
+                        result?;
+                    }
+                    Instruction::TraceVar => {
+                        let name = self.pop()?.get_string();
+                        self.history.entry(name).or_default();
+                    }
+                    Instruction::VarHistory => {
+                        let name = self.pop()?.get_string();
+                        let history = self.history.get(&name).cloned().unwrap_or_default();
+                        self.push(Type::List(history));
+                    }
+                    Instruction::Swap => {
+                        let b = self.pop()?;
+                        let a = self.pop()?;
+                        self.push(b);
+                        self.push(a);
+                    }
+                    Instruction::Copy => {
+                        let a = self.pop()?;
+                        self.push(a.clone());
+                        self.push(a);
+                    }
+                    Instruction::Dup => {
+                        let a = self.pop()?;
+                        self.push(a.clone());
+                        self.push(a);
+                    }
+                    Instruction::Over => {
+                        let b = self.pop()?;
+                        let a = self.pop()?;
+                        self.push(a.clone());
+                        self.push(b);
+                        self.push(a);
+                    }
+                    Instruction::Rot => {
+                        let c = self.pop()?;
+                        let b = self.pop()?;
+                        let a = self.pop()?;
+                        self.push(b);
+                        self.push(c);
+                        self.push(a);
+                    }
+                    Instruction::Pop => {
+                        self.pop()?;
+                    }
+                    Instruction::Insert => {
+                        let new = self.pop()?;
+                        let index = self.pop()?.get_number() as usize;
+                        let target = self.pop()?;
+                        let mut block = target.get_block();
+                        block.insert(index, new);
+                        self.push(target.same_kind(block));
+                    }
+                    Instruction::Change => {
+                        let new = self.pop()?;
+                        let index = self.pop()?.get_number() as usize;
+                        let target = self.pop()?;
+                        let mut block = target.get_block();
+                        block[index] = new;
+                        self.push(target.same_kind(block));
+                    }
+                    Instruction::Delete => {
+                        let index = self.pop()?.get_number() as usize;
+                        let target = self.pop()?;
+                        let mut block = target.get_block();
+                        block.remove(index);
+                        self.push(target.same_kind(block));
+                    }
+                    Instruction::Append => {
+                        let new = self.pop()?;
+                        let target = self.pop()?;
+                        let mut block = target.get_block();
+                        block.push(new);
+                        self.push(target.same_kind(block));
+                    }
+                    Instruction::List => {
+                        let code = self.pop()?.get_block();
+                        let saved = std::mem::take(&mut self.stack);
+                        let result = self.eval(code);
+                        let items = std::mem::replace(&mut self.stack, saved);
+                        result?;
+                        self.push(Type::List(items));
+                    }
+                    Instruction::Push => {
+                        let value = self.pop()?;
+                        let mut list = self.pop()?.get_list();
+                        list.push(value);
+                        self.push(Type::List(list));
+                    }
+                    Instruction::Get => {
+                        let index = self.pop()?.get_number();
+                        let list = self.pop()?.get_list();
+                        let item = match list.get(index as usize) {
+                            Some(item) if index >= 0.0 => item.to_owned(),
+                            _ => Type::Error(Error::IndexOutOfRange),
+                        };
+                        self.push(item);
+                    }
+                    Instruction::Len => {
+                        let len = match self.pop()? {
+                            Type::Dict(dict) => dict.len(),
+                            other => other.get_list().len(),
+                        };
+                        self.push(Type::Int(len as i64));
+                    }
+                    Instruction::ListConcat => {
+                        let b = self.pop()?.get_block();
+                        let target = self.pop()?;
+                        let mut a = target.get_block();
+                        a.extend(b);
+                        self.push(target.same_kind(a));
+                    }
+                    // Unlike insert and delete, out of range indexes are reported as errors
+                    Instruction::ListInsert => {
+                        let new = self.pop()?;
+                        let index = self.pop()?.get_number();
+                        let target = self.pop()?;
+                        let mut list = target.get_block();
+                        if index < 0.0 || index as usize > list.len() {
+                            return Err(Error::IndexOutOfRange);
+                        }
+                        list.insert(index as usize, new);
+                        self.push(target.same_kind(list));
+                    }
+                    Instruction::RemoveAt => {
+                        let index = self.pop()?.get_number();
+                        let target = self.pop()?;
+                        let mut list = target.get_block();
+                        if index < 0.0 || index as usize >= list.len() {
+                            return Err(Error::IndexOutOfRange);
+                        }
+                        list.remove(index as usize);
+                        self.push(target.same_kind(list));
+                    }
+                    // first and last of an empty block are errors, its rest is empty
+                    Instruction::First => {
+                        let list = self.pop()?.get_block();
+                        let first = list.first().cloned();
+                        self.push(first.ok_or(Error::IndexOutOfRange)?);
+                    }
+                    Instruction::Last => {
+                        let list = self.pop()?.get_block();
+                        let last = list.last().cloned();
+                        self.push(last.ok_or(Error::IndexOutOfRange)?);
+                    }
+                    Instruction::Rest => {
+                        let target = self.pop()?;
+                        let list = target.get_block();
+                        let rest = list.get(1..).unwrap_or_default().to_vec();
+                        self.push(target.same_kind(rest));
+                    }
+                    Instruction::Dict => self.push(Type::Dict(HashMap::new())),
+                    Instruction::Set => {
+                        let value = self.pop()?;
+                        let key = self.pop()?.get_string();
+                        let Type::Dict(mut dict) = self.pop()? else {
+                            return Err(Error::TypeMismatch);
+                        };
+                        dict.insert(key, value);
+                        self.push(Type::Dict(dict));
+                    }
+                    Instruction::GetKey => {
+                        let key = self.pop()?.get_string();
+                        let Type::Dict(dict) = self.pop()? else {
+                            return Err(Error::TypeMismatch);
+                        };
+                        let value = dict.get(&key).cloned();
+                        self.push(value.unwrap_or(Type::Error(Error::NotFound)));
+                    }
+                    Instruction::Keys => {
+                        let Type::Dict(dict) = self.pop()? else {
+                            return Err(Error::TypeMismatch);
+                        };
+                        let mut keys: Vec<String> = dict.into_keys().collect();
+                        keys.sort();
+                        self.push(Type::List(keys.into_iter().map(Type::String).collect()));
+                    }
+                    Instruction::Fill => {
+                        let count = self.pop()?.get_number();
+                        let value = self.pop()?;
+                        self.push(Type::List(vec![value; collection_size(count)?]));
+                    }
+                    Instruction::Digits => {
+                        // Fractions are truncated, negative numbers are rejected
+                        let text = match self.pop()? {
+                            Type::Int(n) if n >= 0 => n.to_string(),
+                            Type::Int(_) => return Err(Error::InvalidArgument),
+                            other => {
+                                let number = other.get_number();
+                                if number < 0.0 || !number.is_finite() {
+                                    return Err(Error::InvalidArgument);
+                                }
+                                number.trunc().to_string()
+                            }
+                        };
+                        let digits = text
+                            .chars()
+                            .filter_map(|c| c.to_digit(10))
+                            .map(|d| Type::Int(d as i64))
+                            .collect();
+                        self.push(Type::List(digits));
+                    }
+                    Instruction::Undigits => {
+                        let digits = self.pop()?.get_numbers();
+                        if digits
+                            .iter()
+                            .any(|d| !(0.0..=9.0).contains(d) || d.fract() != 0.0)
+                        {
+                            return Err(Error::InvalidArgument);
+                        }
+                        // Too many digits for an integer falls back to a float, like `add`
+                        let int = digits.iter().try_fold(0i64, |number, d| {
+                            number.checked_mul(10)?.checked_add(*d as i64)
+                        });
+                        self.push(match int {
+                            Some(number) => Type::Int(number),
+                            None => {
+                                Type::Number(digits.iter().fold(0.0, |number, d| number * 10.0 + d))
+                            }
+                        });
+                    }
+                    Instruction::Partition => {
+                        let predicate = self.pop()?.get_block();
+                        let list = self.pop()?.get_block();
+                        let (mut matched, mut rest) = (vec![], vec![]);
+                        for item in list {
+                            if self.call(&predicate, vec![item.clone()])?.get_bool() {
+                                matched.push(item);
+                            } else {
+                                rest.push(item);
+                            }
+                        }
+                        self.push(Type::List(matched));
+                        self.push(Type::List(rest));
+                    }
+                    Instruction::GroupBy => {
+                        let key = self.pop()?.get_block();
+                        let list = self.pop()?.get_block();
+                        let mut groups: Vec<(String, Vec<Type>)> = vec![];
+                        for item in list {
+                            let name = self.call(&key, vec![item.clone()])?.get_string();
+                            match groups.iter_mut().find(|(group, _)| *group == name) {
+                                Some((_, items)) => items.push(item),
+                                None => groups.push((name, vec![item])),
+                            }
+                        }
+                        let result = groups
+                            .into_iter()
+                            .map(|(name, items)| (name, Type::List(items)))
+                            .collect();
+                        self.push(Type::Dict(result));
+                    }
+                    Instruction::TakeWhile => {
+                        let predicate = self.pop()?.get_block();
+                        let list = self.pop()?.get_block();
+                        let mut result = vec![];
+                        for item in list {
+                            if !self.call(&predicate, vec![item.clone()])?.get_bool() {
+                                break;
+                            }
+                            result.push(item);
+                        }
+                        self.push(Type::List(result));
+                    }
+                    Instruction::DropWhile => {
+                        let predicate = self.pop()?.get_block();
+                        let list = self.pop()?.get_block();
+                        let mut skipped = 0;
+                        for item in &list {
+                            if !self.call(&predicate, vec![item.clone()])?.get_bool() {
+                                break;
+                            }
+                            skipped += 1;
+                        }
+                        self.push(Type::List(list[skipped..].to_vec()));
+                    }
+                    Instruction::ZipWith => {
+                        let combiner = self.pop()?.get_block();
+                        let b = self.pop()?.get_block();
+                        let a = self.pop()?.get_block();
+                        let mut result = vec![];
+                        for (a, b) in a.into_iter().zip(b) {
+                            result.push(self.call(&combiner, vec![a, b])?);
+                        }
+                        self.push(Type::List(result));
+                    }
+                    Instruction::Scan => {
+                        let code = self.pop()?.get_block();
+                        let mut accumulator = self.pop()?;
+                        let list = self.pop()?.get_block();
+                        let mut result = vec![];
+                        for item in list {
+                            accumulator = self.call(&code, vec![accumulator, item])?;
+                            result.push(accumulator.clone());
+                        }
+                        self.push(Type::List(result));
+                    }
+                    Instruction::Chunks => {
+                        let size = self.pop()?.get_number();
+                        let list = self.pop()?.get_block();
+                        if !size.is_finite() || size < 1.0 {
+                            return Err(Error::InvalidArgument);
+                        }
+                        let chunks = list
+                            .chunks(size as usize)
+                            .map(|chunk| Type::List(chunk.to_vec()))
+                            .collect();
+                        self.push(Type::List(chunks));
+                    }
+                    Instruction::Windows => {
+                        let size = self.pop()?.get_number();
+                        let list = self.pop()?.get_block();
+                        if !size.is_finite() || size < 1.0 {
+                            return Err(Error::InvalidArgument);
+                        }
+                        let windows = list
+                            .windows(size as usize)
+                            .map(|window| Type::List(window.to_vec()))
+                            .collect();
+                        self.push(Type::List(windows));
+                    }
+                    Instruction::Transpose => {
+                        let rows: Vec<Vec<Type>> = self
+                            .pop()?
+                            .get_block()
+                            .iter()
+                            .map(|row| row.get_block())
+                            .collect();
+                        let columns = transpose(rows).ok_or(Error::DimensionMismatch)?;
+                        self.push(columns);
+                    }
+                    Instruction::MatMul => {
+                        let b = self.pop()?.get_matrix();
+                        let a = self.pop()?.get_matrix();
+                        let product = mat_mul(a, b).ok_or(Error::DimensionMismatch)?;
+                        self.push(product);
+                    }
+                    Instruction::Dot => {
+                        let b = self.pop()?.get_block();
+                        let a = self.pop()?.get_block();
+                        if a.len() != b.len() {
+                            return Err(Error::DimensionMismatch);
+                        }
+                        let product = a
+                            .iter()
+                            .zip(&b)
+                            .map(|(a, b)| a.get_number() * b.get_number());
+                        self.push(Type::Number(product.sum()));
+                    }
+                    // Statistics of an empty block are NaN
+                    Instruction::Mean => {
+                        let numbers = self.pop()?.get_numbers();
+                        self.push(Type::Number(mean(&numbers)));
+                    }
+                    Instruction::Median => {
+                        let mut numbers = self.pop()?.get_numbers();
+                        numbers.sort_by(|a, b| a.total_cmp(b));
+                        let middle = numbers.len() / 2;
+                        let median = if numbers.is_empty() {
+                            f64::NAN
+                        } else if numbers.len().is_multiple_of(2) {
+                            (numbers[middle - 1] + numbers[middle]) / 2.0
+                        } else {
+                            numbers[middle]
+                        };
+                        self.push(Type::Number(median));
+                    }
+                    Instruction::Stddev => {
+                        // Population standard deviation: divides by n, not n - 1
+                        let numbers = self.pop()?.get_numbers();
+                        let average = mean(&numbers);
+                        let deviations: Vec<f64> =
+                            numbers.iter().map(|n| (n - average).powi(2)).collect();
+                        self.push(Type::Number(mean(&deviations).sqrt()));
+                    }
+                    // The sum of an empty block is 0, its max and min are -inf and inf
+                    Instruction::ListSum => {
+                        let numbers = self.pop()?.get_numbers();
+                        self.push(Type::Number(numbers.iter().sum()));
+                    }
+                    Instruction::ListMax => {
+                        let numbers = self.pop()?.get_numbers();
+                        let max = numbers.into_iter().fold(f64::NEG_INFINITY, f64::max);
+                        self.push(Type::Number(max));
+                    }
+                    Instruction::ListMin => {
+                        let numbers = self.pop()?.get_numbers();
+                        let min = numbers.into_iter().fold(f64::INFINITY, f64::min);
+                        self.push(Type::Number(min));
+                    }
+                    Instruction::Enumerate => {
+                        let list = self.pop()?.get_block();
+                        let pairs = list
+                            .into_iter()
+                            .enumerate()
+                            .map(|(i, item)| Type::List(vec![Type::Int(i as i64), item]))
+                            .collect();
+                        self.push(Type::List(pairs));
+                    }
+                    Instruction::Find => {
+                        let predicate = self.pop()?.get_block();
+                        let list = self.pop()?.get_block();
+                        let mut found = Type::Nil;
+                        for item in list {
+                            if self.call(&predicate, vec![item.clone()])?.get_bool() {
+                                found = item;
+                                break;
+                            }
+                        }
+                        self.push(found);
+                    }
+                    Instruction::JsonGet => {
+                        let path = self.pop()?.get_string();
+                        let value = self.pop()?;
+                        self.push(json_get(value, &path));
+                    }
+                    Instruction::FindIndex => {
+                        let predicate = self.pop()?.get_block();
+                        let list = self.pop()?.get_block();
+                        let mut index = -1;
+                        for (i, item) in list.into_iter().enumerate() {
+                            if self.call(&predicate, vec![item])?.get_bool() {
+                                index = i as i64;
+                                break;
+                            }
+                        }
+                        self.push(Type::Int(index));
+                    }
+                    Instruction::All => {
+                        let predicate = self.pop()?.get_block();
+                        let list = self.pop()?.get_block();
+                        let mut result = true;
+                        for item in list {
+                            if !self.call(&predicate, vec![item])?.get_bool() {
+                                result = false;
+                                break;
+                            }
+                        }
+                        self.push(Type::Bool(result));
+                    }
+                    Instruction::Any => {
+                        let predicate = self.pop()?.get_block();
+                        let list = self.pop()?.get_block();
+                        let mut result = false;
+                        for item in list {
+                            if self.call(&predicate, vec![item])?.get_bool() {
+                                result = true;
+                                break;
+                            }
+                        }
+                        self.push(Type::Bool(result));
+                    }
+                    Instruction::CountIf => {
+                        let predicate = self.pop()?.get_block();
+                        let list = self.pop()?.get_block();
+                        let mut count = 0;
+                        for item in list {
+                            if self.call(&predicate, vec![item])?.get_bool() {
+                                count += 1;
+                            }
+                        }
+                        self.push(Type::Int(count));
+                    }
+                    Instruction::Analyze => {
+                        let code = self.pop()?.get_block();
+                        let mut counts = HashMap::new();
+                        count_instructions(&code, &mut counts);
+
+                        let result = counts
+                            .into_iter()
+                            .map(|(name, count)| (name, Type::Int(count as i64)))
+                            .collect();
+                        self.push(Type::Dict(result));
+                    }
+                    Instruction::AssertEmpty => {
+                        if !self.stack.is_empty() {
+                            let items: Vec<String> =
+                                self.stack.iter().map(|i| i.to_source()).collect();
+                            return Err(Error::StackNotEmpty(items.join(" ")));
+                        }
+                    }
+                    Instruction::Label => {
+                        self.pop()?;
+                    }
+                    Instruction::Goto => {
+                        let name = self.pop()?.get_string();
+                        if !self.labels.iter().any(|labels| labels.contains_key(&name)) {
+                            return Err(Error::UnknownLabel);
+                        }
+                        self.jump = Some(name);
+                    }
+                    Instruction::DumpState => {
+                        let path = self.pop()?.get_string();
+                        write(path, self.to_source()).map_err(|_| Error::WriteFailed)?;
+                    }
+                    Instruction::Import => {
+                        let path = self.pop()?.get_string();
+                        self.load_module(&path, |core, program| core.eval(program))?;
+                    }
+                    Instruction::ImportAs => {
+                        let prefix = self.pop()?.get_string();
+                        let path = self.pop()?.get_string();
+
+                        // The module runs with its own memory, whose new names are
+                        // then copied over with the prefix
+                        let builtins = Core::new().memory;
+                        let module = self.load_module(&path, |core, program| {
+                            let saved = std::mem::replace(&mut core.memory, builtins.clone());
+                            let result = core.eval(program);
+                            let module = std::mem::replace(&mut core.memory, saved);
+                            result.map(|_| module)
+                        })?;
+
+                        let names: HashSet<String> = module
+                            .keys()
+                            .filter(|name| !builtins.contains_key(*name))
+                            .cloned()
+                            .collect();
+                        for (name, value) in module {
+                            if names.contains(&name) {
+                                let value = qualify(value, &prefix, &names);
+                                self.memory.insert(format!("{prefix}.{name}"), value);
+                            }
+                        }
+                    }
+                    Instruction::Exit => return Err(Error::Exit),
+                },
+                Type::Variable(name) => {
+                    if let Some(value) = self.memory.get(&name) {
+                        self.push(value.to_owned());
+                    } else if self.undefined_as_nil {
+                        self.push(Type::Nil);
+                    } else {
+                        self.push(Type::Variable(name));
+                    }
+                }
+                Type::Call(name, _) => match self.memory.get(&name) {
+                    Some(Type::Block(code)) => self.eval(code.to_owned())?,
+                    _ => return Err(Error::UndefinedVariable),
+                },
+                other => self.push(other),
+            }
+        }
+    }
+
+    fn to_source(&self) -> String {
+        let mut names: Vec<&String> = self.memory.keys().collect();
+        names.sort();
+
+        let mut source = String::new();
+        for name in names {
+            // Blocks are bound with `def`, so procedures calling each other parse again
+            let binder = match self.memory[name] {
+                Type::Block(_) => "def",
+                _ => "let",
+            };
+            let value = self.memory[name].to_source();
+            source += &format!("{value} \"{}\" {binder}\n", escape(name));
+        }
+        for value in &self.stack {
+            source += &format!("{}\n", value.to_source());
+        }
+        source
+    }
+
+    /// Finds the module as given, or else in the include paths in order.
+    fn resolve_import(&self, path: &str) -> Result<PathBuf, Error> {
+        let candidates = self.include_paths.iter().map(|dir| dir.join(path));
+        std::iter::once(PathBuf::from(path))
+            .chain(candidates)
+            .find(|candidate| candidate.is_file())
+            .ok_or(Error::NotFound)
+    }
+
+    /// Reads and compiles a module, then hands it to `run`. A module that
+    /// imports itself, directly or through others, is a `CyclicImport`.
+    fn load_module<T>(
+        &mut self,
+        path: &str,
+        run: impl FnOnce(&mut Core, Vec<Type>) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let path = self.resolve_import(path)?;
+        let key = path.canonicalize().unwrap_or_else(|_| path.clone());
+        if !self.loading.insert(key.clone()) {
+            return Err(Error::CyclicImport);
+        }
+        let result = read_to_string(&path)
+            .map_err(|_| Error::NotFound)
+            .and_then(|code| self.compile(code).map_err(Error::Parse))
+            .and_then(|program| run(self, program));
+        self.loading.remove(&key);
+        result
+    }
+
+    fn call(&mut self, code: &[Type], args: Vec<Type>) -> Result<Type, Error> {
+        // Run the block on its own stack so it can't disturb the caller's
+        let saved = std::mem::replace(&mut self.stack, args);
+        let result = self.eval(code.to_vec()).and_then(|_| self.pop());
+        self.stack = saved;
+        result
+    }
+
+    fn is_equal(&self, a: &Type, b: &Type) -> bool {
+        match (a, b) {
+            (Type::Number(_) | Type::Int(_), Type::Number(_) | Type::Int(_))
+                if self.epsilon > 0.0 =>
+            {
+                (a.get_number() - b.get_number()).abs() <= self.epsilon
+            }
+            _ => a.equals(b),
+        }
+    }
+
+    fn warn_leak(&self, depth: usize, warned: &mut bool) {
+        if self.warn_leaks && !*warned && self.stack.len() > depth {
+            eprintln!(
+                "Warning! loop iteration grew the stack from {depth} to {} items",
+                self.stack.len()
+            );
+            *warned = true;
+        }
+    }
+
+    fn push(&mut self, value: Type) {
+        self.stack.push(value);
+        if let Some(limit) = self.ring_stack {
+            if self.stack.len() > limit {
+                self.stack.drain(..self.stack.len() - limit);
+            }
+        }
+    }
+
+    fn pop(&mut self) -> Result<Type, Error> {
+        match self.stack.pop() {
+            Some(Type::Error(e)) => Err(e),
+            Some(value) => Ok(value),
+            None => Err(Error::StackEmpty),
+        }
+    }
+}
+
+/// Whether the token is a number literal: an optional leading `-`, digits with an
+/// optional decimal part (`.5` and `5.` included) and an optional exponent.
+/// Subtraction is always the `sub` instruction, so `-5` is never ambiguous.
+fn is_number_literal(token: &str) -> bool {
+    let token = token.strip_prefix('-').unwrap_or(token);
+    let (mantissa, exponent) = match token.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+        None => (token, None),
+    };
+    let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let is_digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+    let exponent_valid = exponent.is_none_or(|exponent| {
+        let digits = exponent.strip_prefix(['-', '+']).unwrap_or(exponent);
+        !digits.is_empty() && is_digits(digits)
+    });
+    (!integer.is_empty() || !fraction.is_empty())
+        && is_digits(integer)
+        && is_digits(fraction)
+        && exponent_valid
+}
+
+fn unescape(text: &str) -> String {
+    let mut result = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some('"') => result.push('"'),
+            Some('\\') => result.push('\\'),
+            // Unknown escapes are kept as they are written
+            Some(other) => {
+                result.push('\\');
+                result.push(other);
+            }
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+fn escape(text: &str) -> String {
+    let mut result = String::new();
+    for c in text.chars() {
+        match c {
+            '\n' => result += "\\n",
+            '\t' => result += "\\t",
+            '\r' => result += "\\r",
+            '"' => result += "\\\"",
+            '\\' => result += "\\\\",
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+fn read_line(prompt: &str) -> Result<String, Error> {
+    // The editor doesn't draw the prompt when stdin is piped
+    if !stdin().is_terminal() {
+        print!("{prompt}");
+        stdout().flush().unwrap_or_default();
+    }
+    match DefaultEditor::new().unwrap().readline(prompt) {
+        Ok(line) => Ok(line),
+        Err(ReadlineError::Interrupted) => Err(Error::Interrupted),
+        Err(_) => Err(Error::EndOfInput),
+    }
+}
+
+fn defined_procedures(code: &[Type], names: &mut HashSet<String>) {
+    for (index, order) in code.iter().enumerate() {
+        match (order, code.get(index + 1)) {
+            (Type::String(name), Some(Type::Instruction(Instruction::Def, _))) => {
+                names.insert(name.to_owned());
+            }
+            (Type::Block(block), _) => defined_procedures(block, names),
+            _ => {}
+        }
+    }
+}
+
+fn check_calls(code: &[Type], known: &HashSet<String>) -> Result<(), ParseError> {
+    for order in code {
+        match order {
+            Type::Call(name, span) if !known.contains(name) => {
+                return Err(ParseError::UnknownWord(name.to_owned(), *span))
+            }
+            Type::Block(block) => check_calls(block, known)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Points references to the module's own names at their prefixed copies.
+fn qualify(value: Type, prefix: &str, names: &HashSet<String>) -> Type {
+    let qualify_all = |items: Vec<Type>| {
+        let mut items = items.into_iter().peekable();
+        let mut result = vec![];
+        while let Some(item) = items.next() {
+            // A name being bound, like `"count" let`, refers to the module's own
+            let binds = matches!(
+                items.peek(),
+                Some(Type::Instruction(
+                    Instruction::Let | Instruction::Def | Instruction::Freeze,
+                    _
+                ))
+            );
+            result.push(match item {
+                Type::String(name) if binds && names.contains(&name) => {
+                    Type::String(format!("{prefix}.{name}"))
+                }
+                item => qualify(item, prefix, names),
+            });
+        }
+        result
+    };
+    match value {
+        Type::Block(block) => Type::Block(qualify_all(block)),
+        Type::List(list) => Type::List(qualify_all(list)),
+        Type::Call(name, span) if names.contains(&name) => {
+            Type::Call(format!("{prefix}.{name}"), span)
+        }
+        Type::Variable(name) if names.contains(&name) => Type::Variable(format!("{prefix}.{name}")),
+        other => other,
+    }
+}
+
+/// Deterministic Miller-Rabin; these bases are enough for every `u64`.
+fn is_prime(n: u64) -> bool {
+    const BASES: [u64; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+    if n < 2 {
+        return false;
+    }
+    if let Some(&base) = BASES.iter().find(|&&base| n.is_multiple_of(base)) {
+        return n == base;
+    }
+
+    let mul_mod = |a: u64, b: u64| (a as u128 * b as u128 % n as u128) as u64;
+    let pow_mod = |mut base: u64, mut exponent: u64| {
+        let mut result = 1;
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result = mul_mod(result, base);
+            }
+            base = mul_mod(base, base);
+            exponent >>= 1;
+        }
+        result
+    };
+
+    let rounds = (n - 1).trailing_zeros();
+    let odd = (n - 1) >> rounds;
+    BASES.iter().all(|&base| {
+        let mut x = pow_mod(base, odd);
+        if x == 1 || x == n - 1 {
+            return true;
+        }
+        for _ in 1..rounds {
+            x = mul_mod(x, x);
+            if x == n - 1 {
+                return true;
+            }
+        }
+        false
+    })
+}
+
+/// The longest collection an instruction may build from a numeric argument.
+const MAX_COLLECTION_SIZE: usize = 1 << 24;
+
+/// Checks a requested collection size, rejecting negative, non-finite and
+/// oversized counts before anything is allocated.
+fn collection_size(count: f64) -> Result<usize, Error> {
+    if count.is_finite() && count >= 0.0 && count <= MAX_COLLECTION_SIZE as f64 {
+        Ok(count as usize)
+    } else {
+        Err(Error::InvalidArgument)
+    }
+}
+
+fn count_instructions(code: &[Type], counts: &mut HashMap<String, usize>) {
+    for order in code {
+        match order {
+            Type::Instruction(instruction, _) => {
+                *counts.entry(instruction.name()).or_default() += 1
+            }
+            Type::Block(block) => count_instructions(block, counts),
+            _ => {}
+        }
+    }
+}
+
+fn transpose(rows: Vec<Vec<Type>>) -> Option<Type> {
+    let width = rows.first().map(|row| row.len()).unwrap_or_default();
+    if rows.iter().any(|row| row.len() != width) {
+        return None;
+    }
+    let columns = (0..width)
+        .map(|i| Type::List(rows.iter().map(|row| row[i].clone()).collect()))
+        .collect();
+    Some(Type::List(columns))
+}
+
+fn mat_mul(a: Vec<Vec<f64>>, b: Vec<Vec<f64>>) -> Option<Type> {
+    let width = b.first().map(|row| row.len()).unwrap_or_default();
+    if a.iter().any(|row| row.len() != b.len()) || b.iter().any(|row| row.len() != width) {
+        return None;
+    }
+    let product = a
+        .iter()
+        .map(|row| {
+            let cells = (0..width)
+                .map(|j| Type::Number(row.iter().zip(&b).map(|(x, b)| x * b[j]).sum()))
+                .collect();
+            Type::List(cells)
+        })
+        .collect();
+    Some(Type::List(product))
+}
+
+/// Applies `int` when both operands are integers and it doesn't overflow,
+/// otherwise `float` on both operands promoted to floats.
+fn arithmetic(
+    a: Type,
+    b: Type,
+    int: impl Fn(i64, i64) -> Option<i64>,
+    float: impl Fn(f64, f64) -> f64,
+) -> Type {
+    if let (Type::Int(a), Type::Int(b)) = (&a, &b) {
+        if let Some(result) = int(*a, *b) {
+            return Type::Int(result);
+        }
+    }
+    Type::Number(float(a.get_number(), b.get_number()))
+}
+
+fn mean(numbers: &[f64]) -> f64 {
+    numbers.iter().sum::<f64>() / numbers.len() as f64
+}
+
+fn format_table(rows: &[Vec<String>]) -> String {
+    // Short rows are padded with empty cells up to the widest row
+    let columns = rows.iter().map(|row| row.len()).max().unwrap_or_default();
+    let widths: Vec<usize> = (0..columns)
+        .map(|i| {
+            rows.iter()
+                .filter_map(|row| row.get(i))
+                .map(|cell| cell.chars().count())
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+
+    let border = widths.iter().fold("+".to_string(), |line, width| {
+        line + &"-".repeat(width + 2) + "+"
+    });
+    let mut table = format!("{border}\n");
+    for row in rows {
+        let mut line = "|".to_string();
+        for (i, width) in widths.iter().enumerate() {
+            let cell = row.get(i).map(String::as_str).unwrap_or_default();
+            line += &format!(" {cell:<width$} |");
+        }
+        table += &format!("{line}\n");
+    }
+    table + &border + "\n"
+}
+
+/// Follows a dotted path such as `a.b.0` through nested values. Dicts are looked
+/// up by key, blocks and lists by index.
+fn json_get(value: Type, path: &str) -> Type {
+    let mut current = value;
+    for key in path.split('.').filter(|key| !key.is_empty()) {
+        current = match (&current, key.parse::<usize>()) {
+            (Type::Dict(dict), _) => match dict.get(key) {
+                Some(found) => found.clone(),
+                None => return Type::Error(Error::NotFound),
+            },
+            (Type::Block(items) | Type::List(items), Ok(index)) => match items.get(index) {
+                Some(item) => item.clone(),
+                None => return Type::Error(Error::IndexOutOfRange),
+            },
+            _ => return Type::Error(Error::NotFound),
+        };
+    }
+    current
+}
+
+fn to_csv(rows: &[Vec<String>]) -> String {
+    let mut csv = String::new();
+    for row in rows {
+        let fields: Vec<String> = row
+            .iter()
+            .map(|field| {
+                if field.contains([',', '"', '\n', '\r']) {
+                    format!("\"{}\"", field.replace('"', "\"\""))
+                } else {
+                    field.to_owned()
+                }
+            })
+            .collect();
+        csv += &format!("{}\n", fields.join(","));
+    }
+    csv
+}
+
+/// Parses CSV text as written by `to_csv`, returning `None` on an unterminated quote.
+fn from_csv(text: &str) -> Option<Vec<Vec<String>>> {
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut field = String::new();
+    let mut in_quote = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quote && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => in_quote = !in_quote,
+            ',' if !in_quote => row.push(std::mem::take(&mut field)),
+            '\r' if !in_quote => {}
+            '\n' if !in_quote => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            c => field.push(c),
+        }
+    }
+    if in_quote {
+        return None;
+    }
+    // A missing newline after the last row is fine
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    Some(rows)
+}
+
+fn url_encode(text: &str) -> String {
+    let mut result = String::new();
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                result.push(byte as char)
+            }
+            byte => result += &format!("%{byte:02X}"),
+        }
+    }
+    result
+}
+
+/// Decodes percent escapes, returning `None` when an escape is malformed
+/// or the decoded bytes aren't valid UTF-8.
+fn url_decode(text: &str) -> Option<String> {
+    let mut bytes = vec![];
+    let mut rest = text.bytes();
+    while let Some(byte) = rest.next() {
+        if byte != b'%' {
+            bytes.push(byte);
+            continue;
+        }
+        let high = (rest.next()? as char).to_digit(16)?;
+        let low = (rest.next()? as char).to_digit(16)?;
+        bytes.push((high * 16 + low) as u8);
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Fetches the body of the URL, reporting failures as error values.
+#[cfg(feature = "http")]
+fn http_get(url: &str) -> Type {
+    let is_timeout = |e: &(dyn std::error::Error + 'static)| {
+        e.downcast_ref::<std::io::Error>()
+            .is_some_and(|e| e.kind() == std::io::ErrorKind::TimedOut)
+    };
+    let agent = ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(30))
+        .build();
+    match agent.get(url).call() {
+        Ok(response) => match response.into_string() {
+            Ok(body) => Type::String(body),
+            Err(e) if is_timeout(&e) => Type::Error(Error::Timeout),
+            Err(_) => Type::Error(Error::RequestFailed),
+        },
+        Err(ureq::Error::Status(code, _)) => Type::Error(Error::HttpStatus(code)),
+        Err(ureq::Error::Transport(e)) if std::error::Error::source(&e).is_some_and(is_timeout) => {
+            Type::Error(Error::Timeout)
+        }
+        Err(_) => Type::Error(Error::RequestFailed),
+    }
+}
+
+#[cfg(not(feature = "http"))]
+fn http_get(_url: &str) -> Type {
+    Type::Error(Error::RequestFailed)
+}
+
+fn group_digits(number: &Type, separator: &str) -> String {
+    let (text, negative) = match number {
+        Type::Int(n) => (n.unsigned_abs().to_string(), *n < 0),
+        other => {
+            let n = other.get_number();
+            (n.abs().to_string(), n.is_sign_negative() && n != 0.0)
+        }
+    };
+    let (integer, fraction) = match text.split_once('.') {
+        Some((integer, fraction)) => (integer.to_string(), format!(".{fraction}")),
+        None => (text.clone(), String::new()),
+    };
+
+    let mut result = String::new();
+    if negative {
+        result.push('-');
+    }
+    for (index, c) in integer.chars().enumerate() {
+        if index != 0 && (integer.len() - index) % 3 == 0 {
+            result += separator;
+        }
+        result.push(c);
+    }
+    result + &fraction
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs the source on a fresh core and shows what is left on the stack.
+    fn stack(source: &str) -> String {
+        let mut core = Core::new();
+        core.eval(Core::parse(source.to_string()).unwrap()).unwrap();
+        let items: Vec<String> = core.stack.iter().map(Type::to_source).collect();
+        items.join(" ")
+    }
+
+    /// Runs the source on a fresh core and returns the error it stops with.
+    fn error(source: &str) -> Error {
+        let mut core = Core::new();
+        match core.eval(Core::parse(source.to_string()).unwrap()) {
+            Err(Error::At(_, e)) => *e,
+            Err(e) => e,
+            Ok(()) => panic!("`{source}` did not fail"),
+        }
+    }
+
+    #[test]
+    fn group_digits_inserts_separators() {
+        assert_eq!(stack("1234567 group-digits"), r#""1,234,567""#);
+        assert_eq!(stack("123 group-digits"), r#""123""#);
+        assert_eq!(stack(r#"1234567 "." group-digits"#), r#""1.234.567""#);
+        assert_eq!(stack("-1234567 group-digits"), r#""-1,234,567""#);
+        assert_eq!(stack("1234.5 group-digits"), r#""1,234.5""#);
+    }
+
+    #[test]
+    fn assert_type_checks_the_type_name() {
+        assert_eq!(stack(r#"5.5 "number" assert-type"#), "5.5");
+        assert_eq!(stack(r#""hi" "string" assert-type"#), r#""hi""#);
+        assert!(matches!(
+            error(r#""hi" "number" assert-type"#),
+            Error::TypeMismatch
+        ));
+    }
+
+    #[test]
+    fn dump_state_parses_back() {
+        let path = std::env::temp_dir().join("stackpp-dump-state-test.spp");
+        let source = format!(
+            r#"1 2.0 "a\"b" 7 "n" let {{ 1 add }} "inc" def {:?} dump-state"#,
+            path.display().to_string()
+        );
+        stack(&source);
+
+        let mut core = Core::new();
+        let dump = read_to_string(&path).unwrap();
+        core.eval(Core::parse(dump).unwrap()).unwrap();
+        let items: Vec<String> = core.stack.iter().map(Type::to_source).collect();
+        assert!(matches!(core.stack[..2], [Type::Int(1), Type::Number(2.0)]));
+        assert_eq!(items.join(" "), r#"1 2.0 "a\"b""#);
+        assert_eq!(core.memory["n"].to_source(), "7");
+        assert_eq!(core.memory["inc"].to_source(), "{ 1 add }");
+    }
+
+    #[test]
+    fn trace_var_records_every_assignment() {
+        let source = r#""i" trace-var 0 "i" let
+            { $i 3 less-than } { $i 1 add "i" let } while
+            "i" var-history"#;
+        assert_eq!(stack(source), "{ 0 1 2 3 } list");
+        assert_eq!(stack(r#""x" var-history len"#), "0");
+    }
+
+    #[test]
+    fn approx_equal_and_epsilon_tolerate_rounding() {
+        assert_eq!(stack("0.1 0.2 add 0.3 0.0001 approx-equal"), "true");
+        assert_eq!(stack("0.1 0.2 add 0.3 equal"), "false");
+
+        let mut core = Core::new();
+        core.epsilon = 0.0001;
+        let program = Core::parse("0.1 0.2 add 0.3 equal".to_string()).unwrap();
+        core.eval(program).unwrap();
+        assert!(matches!(core.stack[..], [Type::Bool(true)]));
+    }
+
+    #[test]
+    fn parses_inf_and_nan_literals() {
+        let program = Core::parse("inf -inf nan".to_string()).unwrap();
+        assert!(matches!(program[0], Type::Number(n) if n == f64::INFINITY));
+        assert!(matches!(program[1], Type::Number(n) if n == f64::NEG_INFINITY));
+        assert!(matches!(program[2], Type::Number(n) if n.is_nan()));
+        assert_eq!(stack("inf -inf nan"), "inf -inf nan");
+    }
+
+    #[test]
+    fn while_count_pushes_the_iteration() {
+        let source = r#"0 "n" let 0 "sum" let
+            { $n 4 less-than } { $sum add "sum" let $n 1 add "n" let } while-count
+            $sum"#;
+        assert_eq!(stack(source), "6");
+    }
+
+    #[test]
+    fn goto_counts_down() {
+        let source = r#"3 "loop" label dup 1 sub dup 0 greater-than { "loop" goto } when"#;
+        assert_eq!(stack(source), "3 2 1 0");
+        assert!(matches!(error(r#""nowhere" goto"#), Error::UnknownLabel));
+    }
+
+    #[test]
+    fn switch_dispatches_by_index() {
+        let branches = r#"{ { "zero" } { "one" } { "two" } } { "other" }"#;
+        assert_eq!(stack(&format!("{branches} 0 switch")), r#""zero""#);
+        assert_eq!(stack(&format!("{branches} 2 switch")), r#""two""#);
+        assert_eq!(stack(&format!("{branches} 3 switch")), r#""other""#);
+        assert_eq!(stack(&format!("{branches} -1 switch")), r#""other""#);
+    }
+
+    #[test]
+    fn when_not_runs_only_for_false() {
+        assert_eq!(stack(r#"false { "ran" } when-not"#), r#""ran""#);
+        assert_eq!(stack(r#"true { "ran" } when-not"#), "");
+    }
+
+    #[test]
+    fn cond_runs_the_first_true_clause() {
+        let source = r#"5 "x" let {
+            { $x 0 less-than } { "negative" }
+            { $x 10 less-than } { "small" }
+            { true } { "large" }
+        } cond"#;
+        assert_eq!(stack(source), r#""small""#);
+    }
+
+    #[test]
+    fn guard_leaves_only_the_current_block() {
+        let source = r#"{ "a" false guard "b" } "f" def f "after""#;
+        assert_eq!(stack(source), r#""a" "after""#);
+        assert_eq!(stack(r#"{ "a" true guard "b" } eval"#), r#""a" "b""#);
+    }
+
+    #[test]
+    fn step_by_builds_an_arithmetic_sequence() {
+        assert_eq!(stack("0 2 4 step-by { 0 2 4 6 } list equal"), "true");
+        assert_eq!(stack("1 0.5 0 step-by len"), "0");
+        assert!(matches!(error("0 1 inf step-by"), Error::InvalidArgument));
+        assert!(matches!(error("0 1 1e12 step-by"), Error::InvalidArgument));
+    }
+
+    #[test]
+    fn parse_int_and_parse_float_report_success() {
+        assert_eq!(stack(r#""42" parse-int dup 0 get swap 1 get"#), "42 true");
+        assert_eq!(stack(r#""abc" parse-int 1 get"#), "false");
+        assert_eq!(
+            stack(r#"" 2.5" parse-float dup 0 get swap 1 get"#),
+            "2.5 true"
+        );
+        assert_eq!(stack(r#""x" parse-float 1 get"#), "false");
+    }
+
+    #[test]
+    fn with_memory_restores_shadowed_names() {
+        let source = r#"1 "x" let dict "x" 2 set "y" 3 set { $x $y } with-memory $x $y"#;
+        assert_eq!(stack(source), "2 3 1 $y");
+        assert!(matches!(
+            error(r#"{ "x" 2 } { $x } with-memory"#),
+            Error::TypeMismatch
+        ));
+    }
+
+    #[test]
+    fn freeze_rejects_reassignment() {
+        assert!(matches!(
+            error(r#"1 "x" let "x" freeze 2 "x" let"#),
+            Error::FrozenVariable
+        ));
+        assert!(matches!(
+            error(r#"1 "x" let "x" freeze dict "x" 2 set { } with-memory"#),
+            Error::FrozenVariable
+        ));
+        assert_eq!(stack(r#"1 "x" let "x" freeze $x"#), "1");
+    }
+
+    #[test]
+    fn rename_var_moves_the_value() {
+        assert_eq!(
+            stack(r#"1 "old" let "old" "new" rename-var $new $old"#),
+            "1 $old"
+        );
+        assert!(matches!(
+            error(r#""missing" "new" rename-var"#),
+            Error::UndefinedVariable
+        ));
+    }
+
+    #[test]
+    fn analyze_counts_instructions_in_nested_blocks() {
+        let source = r#"{ 1 2 add { 3 add } eval print } analyze"#;
+        assert_eq!(stack(&format!(r#"{source} "add" get-key"#)), "2");
+        assert_eq!(stack(&format!(r#"{source} "eval" get-key"#)), "1");
+        assert_eq!(stack(&format!("{source} len")), "3");
+    }
+
+    #[test]
+    fn blocks_compare_by_structure() {
+        assert_eq!(stack("{ 1 2 add } { 1 2 add } equal"), "true");
+        assert_eq!(stack("{ 1 { 2 } } { 1 { 3 } } equal"), "false");
+        assert_eq!(stack("{ 1 2 } { 1 2 3 } equal"), "false");
+        assert_eq!(stack(r#"{ 1 } "foo" def { foo } { foo } equal"#), "true");
+        assert_eq!(
+            stack(r#"{ 1 } "foo" def { 2 } "bar" def { foo } { bar } equal"#),
+            "false"
+        );
+    }
+
+    #[test]
+    fn reverse_string_reverses_characters() {
+        assert_eq!(stack(r#""héllo" reverse-string"#), r#""olléh""#);
+        assert_eq!(stack(r#""" reverse-string"#), r#""""#);
+    }
+
+    #[test]
+    fn count_char_counts_occurrences() {
+        assert_eq!(stack(r#""banana" "a" count-char"#), "3");
+        assert_eq!(stack(r#""banana" "z" count-char"#), "0");
+        assert!(matches!(
+            error(r#""banana" "an" count-char"#),
+            Error::InvalidArgument
+        ));
+        assert!(matches!(
+            error(r#""banana" "" count-char"#),
+            Error::InvalidArgument
+        ));
+    }
+
+    #[test]
+    fn character_predicates_check_the_first_character() {
+        assert_eq!(stack(r#""7" is-digit "x" is-digit"#), "true false");
+        assert_eq!(stack(r#""x" is-alpha "7" is-alpha"#), "true false");
+        assert_eq!(
+            stack(r#"" " is-whitespace "x" is-whitespace"#),
+            "true false"
+        );
+        assert_eq!(stack(r#""" is-digit "7x" is-digit"#), "false true");
+        assert_eq!(stack(r#""½" is-digit "٣" is-digit"#), "false false");
+    }
+
+    #[test]
+    fn partition_splits_by_predicate() {
+        let source = "{ 1 2 3 4 5 } { 2 mod 0 equal } partition";
+        assert_eq!(stack(source), "{ 2 4 } list { 1 3 5 } list");
+    }
+
+    #[test]
+    fn group_by_maps_keys_to_items() {
+        let source = "{ 1 2 3 4 5 } { 2 mod } group-by";
+        assert_eq!(stack(&format!(r#"{source} "1" get-key"#)), "{ 1 3 5 } list");
+        assert_eq!(stack(&format!(r#"{source} "0" get-key"#)), "{ 2 4 } list");
+        assert_eq!(stack(&format!("{source} len")), "2");
+    }
+
+    #[test]
+    fn take_while_and_drop_while_split_at_the_threshold() {
+        let list = "{ 1 2 3 4 5 } { 3 less-than }";
+        assert_eq!(stack(&format!("{list} take-while")), "{ 1 2 } list");
+        assert_eq!(stack(&format!("{list} drop-while")), "{ 3 4 5 } list");
+    }
+
+    #[test]
+    fn zip_with_combines_pairs() {
+        let source = "{ 1 2 3 } { 10 20 30 40 } { add } zip-with";
+        assert_eq!(stack(source), "{ 11 22 33 } list");
+    }
+
+    #[test]
+    fn scan_keeps_running_totals() {
+        assert_eq!(stack("{ 1 2 3 } 0 { add } scan"), "{ 1 3 6 } list");
+    }
+
+    #[test]
+    fn chunks_splits_into_fixed_sizes() {
+        assert_eq!(
+            stack("{ 1 2 3 4 5 } 2 chunks"),
+            "{ { 1 2 } list { 3 4 } list { 5 } list } list"
+        );
+        assert!(matches!(error("{ 1 2 } 0 chunks"), Error::InvalidArgument));
+        assert!(matches!(
+            error("{ 1 2 } nan chunks"),
+            Error::InvalidArgument
+        ));
+    }
+
+    #[test]
+    fn windows_slide_over_the_list() {
+        assert_eq!(
+            stack("{ 1 2 3 4 } 2 windows"),
+            "{ { 1 2 } list { 2 3 } list { 3 4 } list } list"
+        );
+        assert_eq!(stack("{ 1 2 } 3 windows len"), "0");
+        assert!(matches!(error("{ 1 2 } 0 windows"), Error::InvalidArgument));
+        assert!(matches!(
+            error("{ 1 2 } nan windows"),
+            Error::InvalidArgument
+        ));
+    }
+
+    #[test]
+    fn transpose_swaps_rows_and_columns() {
+        assert_eq!(
+            stack("{ { 1 2 } { 3 4 } } transpose"),
+            "{ { 1 3 } list { 2 4 } list } list"
+        );
+        assert!(matches!(
+            error("{ { 1 2 } { 3 } } transpose"),
+            Error::DimensionMismatch
+        ));
+    }
+
+    #[test]
+    fn mat_mul_multiplies_matrices() {
+        assert_eq!(
+            stack("{ { 1 2 } { 3 4 } } { { 5 } { 6 } } mat-mul"),
+            "{ { 17.0 } list { 39.0 } list } list"
+        );
+        assert!(matches!(
+            error("{ { 1 2 } } { { 1 2 } } mat-mul"),
+            Error::DimensionMismatch
+        ));
+    }
+
+    #[test]
+    fn dot_sums_pairwise_products() {
+        assert_eq!(stack("{ 1 2 3 } { 4 5 6 } dot"), "32.0");
+        assert!(matches!(
+            error("{ 1 2 } { 4 5 6 } dot"),
+            Error::DimensionMismatch
+        ));
+    }
+
+    #[test]
+    fn statistics_of_a_small_dataset() {
+        let data = "{ 2 4 4 4 5 5 7 9 }";
+        assert_eq!(stack(&format!("{data} mean")), "5.0");
+        assert_eq!(stack(&format!("{data} median")), "4.5");
+        assert_eq!(stack(&format!("{data} stddev")), "2.0");
+        assert_eq!(stack("{ 3 1 2 } median"), "2.0");
+        assert_eq!(stack("{ } mean { } median"), "nan nan");
+    }
+
+    #[test]
+    fn list_reductions() {
+        assert_eq!(stack("{ 3 1 2 } list-sum"), "6.0");
+        assert_eq!(stack("{ 3 1 2 } list-max"), "3.0");
+        assert_eq!(stack("{ 3 1 2 } list-min"), "1.0");
+    }
+
+    #[test]
+    fn enumerate_pairs_indexes_with_items() {
+        assert_eq!(
+            stack(r#"{ "a" "b" } enumerate"#),
+            r#"{ { 0 "a" } list { 1 "b" } list } list"#
+        );
+    }
+
+    #[test]
+    fn find_and_find_index_locate_the_first_match() {
+        let even = "{ 2 mod 0 equal }";
+        assert_eq!(stack(&format!("{{ 1 4 6 }} {even} find")), "4");
+        assert_eq!(stack(&format!("{{ 1 3 }} {even} find")), "nil");
+        assert_eq!(stack(&format!("{{ 1 4 6 }} {even} find-index")), "1");
+        assert_eq!(stack(&format!("{{ 1 3 }} {even} find-index")), "-1");
+    }
+
+    #[test]
+    fn all_and_any_quantify_over_the_list() {
+        assert_eq!(stack("{ 1 2 3 } { 0 greater-than } all"), "true");
+        assert_eq!(stack("{ 1 -2 3 } { 0 greater-than } all"), "false");
+        assert_eq!(stack("{ 1 -2 3 } { 0 less-than } any"), "true");
+        assert_eq!(stack("{ 1 2 3 } { 0 less-than } any"), "false");
+        assert_eq!(
+            stack("{ } { 0 less-than } all { } { 0 less-than } any"),
+            "true false"
+        );
+    }
+
+    #[test]
+    fn count_if_counts_matches() {
+        assert_eq!(stack("{ 1 5 7 2 9 } { 4 greater-than } count-if"), "3");
+    }
+
+    #[test]
+    fn list_concat_appends_in_order() {
+        assert_eq!(stack("{ 1 2 } { 3 4 } list-concat"), "{ 1 2 3 4 }");
+        assert_eq!(
+            stack("{ 1 2 } list { 3 4 } list-concat"),
+            "{ 1 2 3 4 } list"
+        );
+    }
+
+    #[test]
+    fn list_insert_and_remove_at_edit_by_index() {
+        let list = "{ 1 2 3 } list";
+        assert_eq!(
+            stack(&format!("{list} 0 9 list-insert")),
+            "{ 9 1 2 3 } list"
+        );
+        assert_eq!(
+            stack(&format!("{list} 1 9 list-insert")),
+            "{ 1 9 2 3 } list"
+        );
+        assert_eq!(
+            stack(&format!("{list} 3 9 list-insert")),
+            "{ 1 2 3 9 } list"
+        );
+        assert_eq!(stack(&format!("{list} 0 remove-at")), "{ 2 3 } list");
+        assert_eq!(stack(&format!("{list} 1 remove-at")), "{ 1 3 } list");
+        assert_eq!(stack(&format!("{list} 2 remove-at")), "{ 1 2 } list");
+        assert!(matches!(
+            error(&format!("{list} 4 9 list-insert")),
+            Error::IndexOutOfRange
+        ));
+        assert!(matches!(
+            error(&format!("{list} 3 remove-at")),
+            Error::IndexOutOfRange
+        ));
+    }
+
+    #[test]
+    fn first_last_and_rest() {
+        let list = "{ 1 2 3 } list";
+        assert_eq!(stack(&format!("{list} first")), "1");
+        assert_eq!(stack(&format!("{list} last")), "3");
+        assert_eq!(stack(&format!("{list} rest")), "{ 2 3 } list");
+        assert!(matches!(error("{ } first"), Error::IndexOutOfRange));
+    }
+
+    #[test]
+    fn fill_repeats_a_value() {
+        assert_eq!(stack(r#""x" 3 fill"#), r#"{ "x" "x" "x" } list"#);
+        assert!(matches!(error(r#""x" -1 fill"#), Error::InvalidArgument));
+        assert!(matches!(error(r#""x" inf fill"#), Error::InvalidArgument));
+    }
+
+    #[test]
+    fn digits_splits_an_integer() {
+        assert_eq!(stack("123 digits"), "{ 1 2 3 } list");
+        assert_eq!(stack("12.9 digits"), "{ 1 2 } list");
+        assert_eq!(stack("9007199254740993 digits len"), "16");
+        assert!(matches!(error("-5 digits"), Error::InvalidArgument));
+    }
+
+    #[test]
+    fn undigits_combines_digits() {
+        assert_eq!(stack("{ 1 2 3 } undigits"), "123");
+        assert_eq!(
+            stack("{ 9 9 9 9 9 9 9 9 9 9 9 9 9 9 9 9 9 9 9 9 } undigits"),
+            "100000000000000020000.0"
+        );
+        assert!(matches!(error("{ 1 12 } undigits"), Error::InvalidArgument));
+    }
+
+    #[test]
+    fn stack_shuffling() {
+        assert_eq!(stack("1 dup"), "1 1");
+        assert_eq!(stack("1 2 swap"), "2 1");
+        assert_eq!(stack("1 2 over"), "1 2 1");
+        assert_eq!(stack("1 2 3 rot"), "2 3 1");
+        assert!(matches!(error("1 over"), Error::StackEmpty));
+    }
+
+    #[test]
+    fn is_prime_handles_edges_and_large_inputs() {
+        assert_eq!(
+            stack("2 is-prime 15 is-prime 1 is-prime -7 is-prime"),
+            "true false false false"
+        );
+        assert_eq!(stack("9007199254740881 is-prime"), "true");
+        assert_eq!(stack("9007199254740993 is-prime"), "false");
+        assert_eq!(
+            stack("inf is-prime nan is-prime 1e30 is-prime"),
+            "false false false"
+        );
+    }
+
+    #[test]
+    fn primes_sieves_up_to_the_limit() {
+        assert_eq!(stack("10 primes { 2 3 5 7 } list equal"), "true");
+        assert_eq!(stack("1 primes len"), "0");
+        assert!(matches!(error("inf primes"), Error::InvalidArgument));
+        assert!(matches!(error("1e12 primes"), Error::InvalidArgument));
+    }
+
+    #[test]
+    fn stack_underflow_stops_execution() {
+        let mut core = Core::new();
+        let program = Core::parse(r#"1 add "after""#.to_string()).unwrap();
+        assert!(matches!(
+            core.eval(program),
+            Err(Error::At(_, e)) if matches!(*e, Error::StackEmpty)
+        ));
+        assert!(core.stack.is_empty());
+    }
+
+    #[test]
+    fn fib_is_exact_for_integers_and_stops_at_inf() {
+        assert_eq!(stack("0 fib 1 fib 10 fib"), "0 1 55");
+        assert_eq!(stack("92 fib"), "7540113804746346429");
+        assert_eq!(stack("10.0 fib"), "55.0");
+        assert_eq!(stack("2000 fib 1e300 fib"), "inf inf");
+        assert!(matches!(error("-1 fib"), Error::InvalidArgument));
+    }
+
+    #[test]
+    fn parses_signed_and_scientific_literals() {
+        let program = Core::parse("-0.0 1e-3 .25 -.25 -7".to_string()).unwrap();
+        assert!(matches!(program[0], Type::Number(n) if n == 0.0 && n.is_sign_negative()));
+        assert!(matches!(program[1], Type::Number(n) if n == 0.001));
+        assert!(matches!(program[2], Type::Number(n) if n == 0.25));
+        assert!(matches!(program[3], Type::Number(n) if n == -0.25));
+        assert!(matches!(program[4], Type::Int(-7)));
+        assert_eq!(stack("5 2 sub"), "3");
+    }
+
+    #[test]
+    fn sign_of_numbers() {
+        assert_eq!(stack("-3 sign 0 sign 2.5 sign nan sign"), "-1 0 1.0 nan");
+    }
+
+    #[test]
+    fn comments_parse_like_the_bare_code() {
+        let ast = |source: &str| format!("{:?}", Core::parse(source.to_string()).unwrap());
+        assert_eq!(
+            ast("1 2 add # sum\n{ 3 # three\n mul } eval"),
+            ast("1 2 add\n{ 3\n mul } eval")
+        );
+        assert_eq!(
+            stack(r##""# not a comment" # but this is"##),
+            r##""# not a comment""##
+        );
+    }
+
+    #[test]
+    fn lerp_interpolates_from_start_to_end() {
+        // Operands are pushed as `t end start`
+        assert_eq!(stack("0.5 10 0 lerp"), "5.0");
+        assert_eq!(stack("0.25 10 2 lerp"), "4.0");
+    }
+
+    #[test]
+    fn string_escapes_are_decoded() {
+        let program = Core::parse(r#""a\nb\t\r\"c\"\\ \q""#.to_string()).unwrap();
+        assert!(matches!(&program[0], Type::String(s) if s == "a\nb\t\r\"c\"\\ \\q"));
+        assert_eq!(stack(r#""a\nb\"""#), r#""a\nb\"""#);
+    }
+
+    #[test]
+    fn degree_radian_conversions() {
+        assert_eq!(stack("180 deg-to-rad"), std::f64::consts::PI.to_string());
+        assert_eq!(stack("3.141592653589793 rad-to-deg"), "180.0");
+    }
+
+    #[test]
+    fn parses_boolean_literals() {
+        let program = Core::parse("true false".to_string()).unwrap();
+        assert!(matches!(program[..], [Type::Bool(true), Type::Bool(false)]));
+        assert_eq!(stack(r#"true { "yes" } { "no" } if-else"#), r#""yes""#);
+    }
+
+    #[test]
+    fn hypot_of_a_right_triangle() {
+        assert_eq!(stack("3 4 hypot"), "5.0");
+    }
+
+    #[test]
+    fn logical_operators() {
+        assert_eq!(stack("true not false not"), "false true");
+        assert_eq!(stack("true true and true false and"), "true false");
+        assert_eq!(stack("false true or false false or"), "true false");
+    }
+
+    #[test]
+    fn inclusive_comparisons() {
+        assert_eq!(
+            stack("1 2 less-than-equal 2 2 less-than-equal 3 2 less-than-equal"),
+            "true true false"
+        );
+        assert_eq!(
+            stack("1 2 greater-than-equal 2 2 greater-than-equal"),
+            "false true"
+        );
+    }
+
+    #[test]
+    fn atan2_uses_both_signs() {
+        assert_eq!(stack("1 1 atan2"), std::f64::consts::FRAC_PI_4.to_string());
+        assert_eq!(stack("0 -1 atan2"), std::f64::consts::PI.to_string());
+    }
+
+    #[test]
+    fn list_push_get_and_len() {
+        assert_eq!(stack("{ 1 2 } list 3 push"), "{ 1 2 3 } list");
+        assert_eq!(stack("{ 1 2 3 } list 2 get"), "3");
+        assert_eq!(stack("{ 1 2 3 } list len"), "3");
+        assert_eq!(stack("{ 1 } list 5 get"), r#""indexoutofrange""#);
+        assert_eq!(stack("{ 1 1 add } list"), "{ 2 } list");
+    }
+
+    #[test]
+    fn collection_instructions_return_lists() {
+        assert_eq!(stack("10 primes { 2 3 5 7 } list equal"), "true");
+        assert_eq!(stack("{ 1 2 3 } list 0 remove-at"), "{ 2 3 } list");
+        assert_eq!(stack("{ 1 2 3 } 0 remove-at"), "{ 2 3 }");
+    }
+
+    #[test]
+    fn ring_stack_keeps_the_newest_items() {
+        let mut core = Core::new();
+        core.ring_stack = Some(2);
+        core.eval(Core::parse("1 2 3 4".to_string()).unwrap())
+            .unwrap();
+        assert!(matches!(core.stack[..], [Type::Int(3), Type::Int(4)]));
+    }
+
+    #[test]
+    fn length_and_substring_count_characters() {
+        assert_eq!(stack(r#""こんにちは" length"#), "5");
+        assert_eq!(stack(r#""こんにちは" 1 3 substring"#), r#""んに""#);
+        assert_eq!(stack(r#""こんにちは" 3 99 substring"#), r#""ちは""#);
+        assert_eq!(stack(r#""abc" 2 1 substring"#), r#""""#);
+    }
+
+    #[test]
+    fn elif_desugars_to_nested_if_else() {
+        let ast = |source: &str| {
+            let program = Core::parse(source.to_string()).unwrap();
+            let items: Vec<String> = program.iter().map(Type::to_source).collect();
+            items.join(" ")
+        };
+        assert_eq!(
+            ast(r#"false { 1 } elif true { 2 } { 3 } if-else"#),
+            ast(r#"false { 1 } { true { 2 } { 3 } if-else } if-else"#)
+        );
+        assert_eq!(
+            ast(r#"false { 1 } elif false { 2 } elif true { 3 } { 4 } if-else"#),
+            ast(r#"false { 1 } { false { 2 } { true { 3 } { 4 } if-else } if-else } if-else"#)
+        );
+        assert_eq!(stack("false { 1 } elif true { 2 } { 3 } if-else"), "2");
+        assert!(matches!(
+            Core::parse("true { 1 } elif false { 2 }".to_string()),
+            Err(ParseError::UnterminatedElif(_))
+        ));
+    }
+
+    #[test]
+    fn strip_comments_keeps_strings() {
+        assert_eq!(Core::strip_comments("1 2 add # sum\n3"), "1 2 add \n3");
+        assert_eq!(
+            Core::strip_comments(r#""a # b" print # done"#),
+            r#""a # b" print "#
+        );
+        assert_eq!(
+            Core::strip_comments(r##""say \"#\"" # x"##),
+            r##""say \"#\"" "##
+        );
+        assert_eq!(Core::strip_comments("{ 1 # one\n}"), "{ 1 \n}");
+    }
+
+    #[test]
+    fn format_table_aligns_columns() {
+        let rows = vec![
+            vec!["name".to_string(), "qty".to_string()],
+            vec!["apple".to_string(), "3".to_string()],
+            vec!["kiwi".to_string()],
+        ];
+        let expected = "\
++-------+-----+
+| name  | qty |
+| apple | 3   |
+| kiwi  |     |
++-------+-----+
+";
+        assert_eq!(format_table(&rows), expected);
+    }
+
+    #[test]
+    fn equal_compares_by_type() {
+        assert_eq!(stack(r#"1 "1" equal true "true" equal"#), "false false");
+        assert_eq!(
+            stack(r#"1 1.0 equal "a" "a" equal nil nil equal"#),
+            "true true true"
+        );
+        assert_eq!(
+            stack("{ 1 } { 1 } list equal { 1 } list { 1 } list equal"),
+            "false true"
+        );
+    }
+
+    #[test]
+    fn not_equal_negates_equal() {
+        assert_eq!(stack("1 2 not-equal 2 2 not-equal"), "true false");
+        assert_eq!(
+            stack(r#""a" "b" not-equal "a" "a" not-equal"#),
+            "true false"
+        );
+        assert_eq!(
+            stack("true false not-equal true true not-equal"),
+            "true false"
+        );
+    }
+
+    #[test]
+    fn csv_round_trips_quoted_fields() {
+        let rows = r#"{ { "a" "b,c" } { "q\"x" "line\nbreak" } }"#;
+        assert_eq!(
+            stack(&format!("{rows} to-csv")),
+            r#""a,\"b,c\"\n\"q\"\"x\",\"line\nbreak\"\n""#
+        );
+        assert_eq!(
+            stack(&format!("{rows} to-csv from-csv")),
+            r#"{ { "a" "b,c" } list { "q\"x" "line\nbreak" } list } list"#
+        );
+        assert!(matches!(
+            error(r#""\"open" from-csv"#),
+            Error::InvalidArgument
+        ));
+    }
+
+    #[test]
+    fn def_procedures_can_recurse() {
+        let source = r#"{ dup 1 greater-than { dup 1 sub factorial mul } when } "factorial" def
+            5 factorial"#;
+        assert_eq!(stack(source), "120");
+    }
+
+    #[test]
+    fn url_encoding_round_trips() {
+        assert_eq!(stack(r#""a b/é" url-encode"#), r#""a%20b%2F%C3%A9""#);
+        assert_eq!(stack(r#""a b/é" url-encode url-decode"#), r#""a b/é""#);
+        assert!(matches!(
+            error(r#""%zz" url-decode"#),
+            Error::InvalidArgument
+        ));
+    }
+
+    #[test]
+    fn nil_reads_as_empty_values() {
+        assert_eq!(stack(r#"nil "" concat nil not nil len"#), r#""" true 0"#);
+
+        let mut core = Core::new();
+        core.undefined_as_nil = true;
+        core.eval(Core::parse("$missing".to_string()).unwrap())
+            .unwrap();
+        assert!(matches!(core.stack[..], [Type::Nil]));
+        assert_eq!(stack("$missing"), "$missing");
+    }
+
+    /// Answers a single request with the given raw response.
+    #[cfg(feature = "http")]
+    fn mock_server(response: &'static str) -> String {
+        use std::{io::Read, net::TcpListener, thread};
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = vec![];
+            let mut buffer = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let read = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..read]);
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+        format!("http://{address}/")
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn http_get_reads_the_body_and_reports_statuses() {
+        let url = mock_server("HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello");
+        assert_eq!(stack(&format!("{url:?} http-get")), r#""hello""#);
+
+        let url = mock_server("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n");
+        assert_eq!(stack(&format!("{url:?} http-get")), r#""httpstatus(404)""#);
+
+        let url = mock_server("HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n");
+        assert!(matches!(
+            error(&format!("{url:?} http-get 1 add")),
+            Error::HttpStatus(500)
+        ));
+    }
+
+    #[test]
+    fn json_get_walks_dicts_and_lists() {
+        let data = r#"dict "a" dict "b" { 10 20 } list set set"#;
+        assert_eq!(stack(&format!(r#"{data} "a.b.1" json-get"#)), "20");
+        assert_eq!(stack(&format!(r#"{data} "a.x" json-get"#)), r#""notfound""#);
+        assert_eq!(
+            stack(&format!(r#"{data} "a.b.5" json-get"#)),
+            r#""indexoutofrange""#
+        );
+        assert_eq!(stack(r#"{ 1 9 0 7 } "0" json-get"#), "1");
+        assert_eq!(stack(r#"{ "k" 7 } "k" json-get"#), r#""notfound""#);
+    }
+
+    #[test]
+    fn unknown_words_are_parse_errors() {
+        let error = Core::parse("2 3 addd".to_string()).unwrap_err();
+        assert!(matches!(&error, ParseError::UnknownWord(word, _) if word == "addd"));
+        assert!(error.to_string().contains("addd"));
+        assert!(Core::parse("2 3 add".to_string()).is_ok());
+    }
+
+    #[test]
+    fn with_timeout_aborts_an_infinite_loop() {
+        assert!(matches!(
+            error("{ { true } { } while } 10 with-timeout"),
+            Error::Timeout
+        ));
+        assert_eq!(stack("{ 1 2 add } 1000 with-timeout"), "3");
+    }
+
+    #[test]
+    fn unterminated_delimiters_are_parse_errors() {
+        assert!(matches!(
+            Core::parse(r#""abc"#.to_string()),
+            Err(ParseError::UnterminatedString(_))
+        ));
+        assert!(matches!(
+            Core::parse("{ 1 2 add".to_string()),
+            Err(ParseError::UnterminatedBlock(_))
+        ));
+    }
+
+    #[test]
+    fn catch_handles_only_the_matching_kind() {
+        let source = r#"{ pop } { "caught" } "StackEmpty" catch"#;
+        assert_eq!(stack(source), r#""stackempty" "caught""#);
+        assert!(matches!(
+            error(r#"{ 1 0 div } { "caught" } "StackEmpty" catch"#),
+            Error::DivideByZero
+        ));
+        assert_eq!(
+            stack(r#"{ 7 0 idiv } { } "DivideByZero" catch"#),
+            r#""dividebyzero""#
+        );
+        assert!(matches!(error("5 0 mod"), Error::DivideByZero));
+    }
+
+    #[test]
+    fn integers_stay_exact_and_promote_to_floats() {
+        assert_eq!(stack("7 2 idiv"), "3");
+        assert_eq!(stack("1 2.5 add 2 3 add"), "3.5 5");
+        assert_eq!(stack("9007199254740993 1 add"), "9007199254740994");
+        assert_eq!(
+            stack("1234567890123456789 group-digits"),
+            r#""1,234,567,890,123,456,789""#
+        );
+    }
+
+    #[test]
+    fn raise_is_caught_with_its_message() {
+        assert_eq!(stack(r#"{ "boom" raise } { } try"#), r#""boom""#);
+        assert_eq!(stack(r#"{ "boom" raise } { } "Raised" catch"#), r#""boom""#);
+        assert!(matches!(error(r#""boom" raise"#), Error::Raised(m) if m == "boom"));
+    }
+
+    #[test]
+    fn rounding_and_roots() {
+        assert_eq!(stack("-2.5 floor -2.5 ceil"), "-3.0 -2.0");
+        assert_eq!(stack("2.5 round -2.5 round 2.4 round"), "3.0 -3.0 2.0");
+        assert_eq!(stack("-3 abs 9 sqrt -4 sqrt"), "3 3.0 nan");
+        assert_eq!(stack("7 floor 7 ceil 7 round -7 abs"), "7 7 7 7");
+        assert_eq!(
+            stack("-9223372036854775807 1 sub abs"),
+            "9223372036854776000.0"
+        );
+    }
+
+    #[test]
+    fn try_finally_runs_the_cleanup_either_way() {
+        let source = r#"{ "ok" } { } { "cleaned" "state" let } try-finally $state"#;
+        assert_eq!(stack(source), r#""ok" "cleaned""#);
+        let source = r#"{ 1 0 div } { } { "cleaned" "state" let } try-finally $state"#;
+        assert_eq!(stack(source), r#""dividebyzero" "cleaned""#);
+
+        let mut core = Core::new();
+        let source = r#"{ 1 0 div } { pop "again" raise } { "cleaned" "state" let } try-finally"#;
+        assert!(core.eval(Core::parse(source.to_string()).unwrap()).is_err());
+        assert!(matches!(core.memory.get("state"), Some(Type::String(s)) if s == "cleaned"));
+
+        // try always takes two blocks, whatever is below them
+        assert_eq!(stack(r#"5 { 1 } { } try"#), "5 1");
+        assert_eq!(stack(r#"{ 1 } { 2 } { 3 } try"#), "{ 1 } 2");
+
+        let source = r#"{ "out" goto } { } { "fin" "state" let } try-finally 1 "out" label $state"#;
+        assert_eq!(stack(source), r#""fin""#);
+    }
+
+    #[test]
+    fn min_and_max_ignore_nan() {
+        assert_eq!(stack("-1 1 min -1 1 max"), "-1.0 1.0");
+        assert_eq!(stack("2 2 min 2 2 max"), "2.0 2.0");
+        assert_eq!(stack("nan 3 min 3 nan max"), "3.0 3.0");
+    }
+
+    #[test]
+    fn retry_repeats_until_success() {
+        let flaky = r#"0 "n" let { $n 1 add "n" let $n 3 less-than { "fail" raise } when "ok" }"#;
+        assert_eq!(stack(&format!("{flaky} 3 retry $n")), r#""ok" 3"#);
+        assert_eq!(stack(&format!("{flaky} 2 retry $n")), r#""fail" 2"#);
+        assert!(matches!(error("{ } 0 retry"), Error::InvalidArgument));
+    }
+
+    #[test]
+    fn negate_flips_the_sign() {
+        assert_eq!(stack("5 negate 0 negate -2.5 negate"), "-5 0 2.5");
+    }
+
+    #[test]
+    fn cyclic_imports_are_reported() {
+        let dir = std::env::temp_dir().join("stackpp-cyclic-import-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        write(dir.join("a.spp"), r#""b.spp" import"#).unwrap();
+        write(dir.join("b.spp"), r#""a.spp" import"#).unwrap();
+        write(dir.join("c.spp"), r#"1 "x" let"#).unwrap();
+
+        let mut core = Core::new();
+        core.include_paths = vec![dir];
+        let program = Core::parse(r#""a.spp" import"#.to_string()).unwrap();
+        assert!(matches!(
+            core.eval(program),
+            Err(Error::At(_, e)) if matches!(*e, Error::CyclicImport)
+        ));
+
+        // Importing the same module again after it has loaded is fine
+        let program = Core::parse(r#""c.spp" import "c.spp" import $x"#.to_string()).unwrap();
+        core.eval(program).unwrap();
+        assert!(matches!(core.stack[..], [Type::Int(1)]));
+    }
+
+    #[test]
+    fn errors_report_their_line_and_column() {
+        let mut core = Core::new();
+        let program = Core::parse("1\n{\n  pop pop\n} eval".to_string()).unwrap();
+        let error = core.eval(program).unwrap_err();
+        assert!(matches!(error, Error::At(Span { line: 3, column: 7 }, _)));
+        assert_eq!(error.to_string(), "StackEmpty at line 3, column 7");
+
+        let error = Core::parse("1 2\n  nope".to_string()).unwrap_err();
+        assert_eq!(error.to_string(), "unknown word `nope` at line 2, column 3");
+    }
+
+    #[test]
+    fn import_as_keeps_each_prefix_separate() {
+        let dir = std::env::temp_dir().join("stackpp-import-as-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let module = dir.join("counter.spp");
+        write(
+            &module,
+            r#"0 "count" let { $count 1 add "count" let } "inc" def"#,
+        )
+        .unwrap();
+
+        let mut core = Core::new();
+        let source = format!(
+            r#"{path:?} "a" import-as {path:?} "b" import-as a.inc a.inc b.inc $a.count $b.count"#,
+            path = module.display().to_string()
+        );
+        core.eval(core.compile(source).unwrap()).unwrap();
+        assert!(matches!(core.stack[..], [Type::Int(2), Type::Int(1)]));
+        assert!(!core.memory.contains_key("count"));
+    }
+
+    #[test]
+    fn dicts_store_and_read_back_values() {
+        let data = r#"dict "a" 1 set "b" "two" set"#;
+        assert_eq!(stack(&format!(r#"{data} "a" get-key"#)), "1");
+        assert_eq!(stack(&format!(r#"{data} "b" get-key"#)), r#""two""#);
+        assert_eq!(stack(&format!(r#"{data} "c" get-key"#)), r#""notfound""#);
+        assert_eq!(stack(&format!("{data} keys")), r#"{ "a" "b" } list"#);
+        assert_eq!(stack(&format!("{data} len")), "2");
+        assert_eq!(stack(r#"dict "a" 1 set len"#), "1");
+        assert!(matches!(error(r#"{ } "a" 1 set"#), Error::TypeMismatch));
+    }
+
+    #[test]
+    fn dicts_work_as_bindings_and_results() {
+        let source = r#"1 "x" let dict "x" 2 set { $x } with-memory $x"#;
+        assert_eq!(stack(source), "2 1");
+        let source = r#"{ 1 2 3 } { 2 mod } group-by "1" get-key"#;
+        assert_eq!(stack(source), "{ 1 3 } list");
+        assert_eq!(stack(r#"{ 1 2 add } analyze "add" get-key"#), "1");
+    }
+
+    #[test]
+    fn assert_empty_fails_with_the_leftover_items() {
+        assert!(matches!(
+            error(r#"1 "two" assert-empty"#),
+            Error::StackNotEmpty(items) if items == r#"1 "two""#
+        ));
+        assert_eq!(stack("1 pop assert-empty"), "");
+    }
+
+    #[test]
+    fn exit_is_not_caught_as_a_failure() {
+        assert!(matches!(error("{ exit } { 1 } try"), Error::Exit));
+        assert!(matches!(error("{ exit } 3 retry"), Error::Exit));
+    }
+}
@@ -1,6 +1,7 @@
 use clap::Parser;
-use rustyline::DefaultEditor;
-use std::{collections::HashMap, fs::read_to_string, process::exit};
+use rustyline::{error::ReadlineError, DefaultEditor};
+use stackpp::{Core, Error};
+use std::{env::var, fs::read_to_string, path::PathBuf, process::exit};
 
 const VERSION: &str = "0.2.0";
 
@@ -15,405 +16,141 @@ struct Cli {
     /// Run the script file
     #[arg(index = 1)]
     file: Option<String>,
+
+    /// Run the given code instead of a file
+    #[arg(short, long, conflicts_with = "file")]
+    eval: Option<String>,
+
+    /// Warn when a loop iteration grows the stack
+    #[arg(long)]
+    warn_leaks: bool,
+
+    /// Tolerance used by `equal` when comparing numbers
+    #[arg(long, default_value_t = 0.0)]
+    epsilon: f64,
+
+    /// Add a directory to search for imported modules
+    #[arg(short = 'I', long = "include", value_name = "DIR")]
+    include: Vec<PathBuf>,
+
+    /// Keep only the newest N items on the stack
+    #[arg(long, value_name = "N")]
+    ring_stack: Option<usize>,
+
+    /// Push nil for undefined variables instead of their name
+    #[arg(long)]
+    undefined_as_nil: bool,
 }
 
 fn main() {
     let cli = Cli::parse();
-    let mut rl = DefaultEditor::new().unwrap();
-    let mut stackpp = Core {
-        stack: vec![],
-        memory: HashMap::from([
-            ("new-line".to_string(), Type::String("\n".to_string())),
-            ("double-quote".to_string(), Type::String("\"".to_string())),
-            ("tab".to_string(), Type::String("\t".to_string())),
-        ]),
-    };
-
-    if let Some(path) = cli.file {
+    let mut stackpp = Core::new();
+    stackpp.warn_leaks = cli.warn_leaks;
+    stackpp.epsilon = cli.epsilon;
+    stackpp.ring_stack = cli.ring_stack;
+    stackpp.undefined_as_nil = cli.undefined_as_nil;
+    stackpp.include_paths = cli.include;
+
+    if let Some(code) = cli.eval {
+        run_or_exit(&mut stackpp, code);
+        println!("Result : {stackpp:?}");
+    } else if let Some(path) = cli.file {
         if let Ok(code) = read_to_string(path) {
-            stackpp.eval(Core::parse(code));
+            run_or_exit(&mut stackpp, code);
         } else {
             eprintln!("Error! it fault to open the file");
         }
     } else {
-        println!("Stack++");
-        loop {
-            let mut code = String::new();
-            loop {
-                let enter = rl.readline("> ").unwrap_or_default();
-                code += &format!("{enter}\n");
-                if enter.is_empty() {
-                    break;
-                }
-            }
-
-            let program = Core::parse(code.to_string());
-            println!("AST    : {program:?}");
-            stackpp.eval(program);
-            println!("Result : {stackpp:?}");
-        }
+        let rc = load_rc(&mut stackpp);
+        repl(&mut stackpp, rc.as_deref());
     }
 }
 
-#[derive(Clone, Debug)]
-enum Type {
-    Number(f64),
-    String(String),
-    Bool(bool),
-    Variable(String),
-    Instruction(Instruction),
-    Block(Vec<Type>),
-    Error(Error),
-}
-
-impl Type {
-    fn get_number(&self) -> f64 {
-        match self {
-            Type::Number(n) => n.to_owned(),
-            _ => 0.0,
-        }
+/// Evaluates `~/.stackpprc` if there is one, returning its path when it was loaded.
+fn load_rc(stackpp: &mut Core) -> Option<String> {
+    let path = format!("{}/.stackpprc", var("HOME").ok()?);
+    let code = read_to_string(&path).ok()?;
+    match stackpp
+        .compile(code)
+        .map_err(Error::Parse)
+        .and_then(|program| stackpp.eval(program))
+    {
+        Err(Error::Exit) => exit(0),
+        Err(e) => eprintln!("Warning! {path} failed: {e}"),
+        Ok(()) => {}
     }
-
-    fn get_string(&self) -> String {
-        match self {
-            Type::String(s) | Type::Variable(s) => s.to_owned(),
-            Type::Number(n) => n.to_string(),
-            Type::Error(e) => format!("{e:?}").to_lowercase(),
-            _ => String::new(),
-        }
-    }
-
-    fn get_bool(&self) -> bool {
-        match self {
-            Type::Bool(n) => n.to_owned(),
-            _ => false,
-        }
-    }
-
-    fn get_block(&self) -> Vec<Type> {
-        match self {
-            Type::Block(b) => b.to_owned(),
-            other => vec![other.to_owned()],
-        }
-    }
-}
-
-#[derive(Clone, Debug)]
-enum Error {
-    StackEmpty,
-}
-
-#[derive(Clone, Debug)]
-enum Instruction {
-    Add,
-    Sub,
-    Mul,
-    Div,
-    Mod,
-    Pow,
-    Concat,
-    Print,
-    Input,
-    Equal,
-    LessThan,
-    GreaterThan,
-    Eval,
-    When,
-    IfElse,
-    While,
-    Until,
-    Let,
-    Swap,
-    Copy,
-    Pop,
-    Insert,
-    Change,
-    Delete,
-    Append,
-    Exit,
+    Some(path)
 }
 
-#[derive(Clone, Debug)]
-struct Core {
-    stack: Vec<Type>,
-    memory: HashMap<String, Type>,
-}
-
-impl Core {
-    fn parse(source: String) -> Vec<Type> {
-        fn tokenize_expr(input: String) -> Vec<String> {
-            let mut tokens = Vec::new();
-            let mut current_token = String::new();
-            let mut in_parentheses: usize = 0;
-            let mut in_quote = false;
-
-            for c in input.chars() {
-                match c {
-                    '{' if !in_quote => {
-                        in_parentheses += 1;
-                        current_token.push(c);
-                    }
-                    '}' if !in_quote => {
-                        if in_parentheses != 0 {
-                            current_token.push(c);
-                            in_parentheses -= 1;
-                            if in_parentheses == 0 {
-                                tokens.push(current_token.clone());
-                                current_token.clear();
-                            }
-                        }
-                    }
-                    '"' => {
-                        if in_parentheses == 0 {
-                            if in_quote {
-                                current_token.push(c);
-                                in_quote = false;
-                                tokens.push(current_token.clone());
-                                current_token.clear();
-                            } else {
-                                in_quote = true;
-                                current_token.push(c);
-                            }
-                        } else {
-                            current_token.push(c);
-                        }
-                    }
-                    ' ' | '　' | '\n' | '\t' | '\r' => {
-                        if in_parentheses != 0 || in_quote {
-                            current_token.push(c);
-                        } else if !current_token.is_empty() {
-                            tokens.push(current_token.clone());
-                            current_token.clear();
-                        }
-                    }
-                    _ => {
-                        current_token.push(c);
-                    }
-                }
-            }
-
-            if !(in_parentheses != 0 || in_quote || current_token.is_empty()) {
-                tokens.push(current_token);
+/// Runs the interactive loop; `startup` is the file loaded beforehand, if any.
+fn repl(stackpp: &mut Core, startup: Option<&str>) {
+    let mut rl = DefaultEditor::new().unwrap();
+    // History is kept across sessions in ~/.stackpp_history; a missing file is fine
+    let history = var("HOME")
+        .ok()
+        .map(|home| format!("{home}/.stackpp_history"));
+    if let Some(path) = &history {
+        let _ = rl.load_history(path);
+    }
+    let variables = stackpp
+        .memory
+        .len()
+        .saturating_sub(Core::new().memory.len());
+    println!("{}", banner(startup, variables));
+    // Only the REPL reads from the editor; file and -e mode never reach here
+    'repl: loop {
+        let mut code = String::new();
+        loop {
+            let enter = match rl.readline("> ") {
+                Ok(enter) => enter,
+                // Ctrl-C drops the entry being typed, Ctrl-D quits
+                Err(ReadlineError::Interrupted) => continue 'repl,
+                Err(_) => break 'repl,
+            };
+            code += &format!("{enter}\n");
+            if enter.is_empty() {
+                break;
             }
-            tokens
         }
-        let mut result = vec![];
-        for token in tokenize_expr(source) {
-            let mut token = token.trim().to_string();
-            if let Ok(n) = token.parse::<f64>() {
-                result.push(Type::Number(n));
-            } else if token.starts_with('"') && token.ends_with('"') {
-                token.remove(token.find('"').unwrap_or_default());
-                token.remove(token.rfind('"').unwrap_or_default());
-                result.push(Type::String(token));
-            } else if token.starts_with("{") && token.ends_with("}") {
-                token.remove(token.find('{').unwrap_or_default());
-                token.remove(token.rfind('}').unwrap_or_default());
-                result.push(Type::Block(Core::parse(token)));
-            } else if token.starts_with("$") {
-                token.remove(token.find('$').unwrap_or_default());
-                result.push(Type::Variable(token));
-            } else {
-                match token.as_str() {
-                    "add" => result.push(Type::Instruction(Instruction::Add)),
-                    "sub" => result.push(Type::Instruction(Instruction::Sub)),
-                    "mul" => result.push(Type::Instruction(Instruction::Mul)),
-                    "div" => result.push(Type::Instruction(Instruction::Div)),
-                    "mod" => result.push(Type::Instruction(Instruction::Mod)),
-                    "pow" => result.push(Type::Instruction(Instruction::Pow)),
-                    "concat" => result.push(Type::Instruction(Instruction::Concat)),
-                    "print" => result.push(Type::Instruction(Instruction::Print)),
-                    "input" => result.push(Type::Instruction(Instruction::Input)),
-                    "equal" => result.push(Type::Instruction(Instruction::Equal)),
-                    "less-than" => result.push(Type::Instruction(Instruction::LessThan)),
-                    "greater-than" => result.push(Type::Instruction(Instruction::GreaterThan)),
-                    "eval" => result.push(Type::Instruction(Instruction::Eval)),
-                    "when" => result.push(Type::Instruction(Instruction::When)),
-                    "if-else" => result.push(Type::Instruction(Instruction::IfElse)),
-                    "while" => result.push(Type::Instruction(Instruction::While)),
-                    "until" => result.push(Type::Instruction(Instruction::Until)),
-                    "let" => result.push(Type::Instruction(Instruction::Let)),
-                    "swap" => result.push(Type::Instruction(Instruction::Swap)),
-                    "copy" => result.push(Type::Instruction(Instruction::Copy)),
-                    "pop" => result.push(Type::Instruction(Instruction::Pop)),
-                    "insert" => result.push(Type::Instruction(Instruction::Insert)),
-                    "change" => result.push(Type::Instruction(Instruction::Change)),
-                    "delete" => result.push(Type::Instruction(Instruction::Delete)),
-                    "append" => result.push(Type::Instruction(Instruction::Append)),
-                    "exit" => result.push(Type::Instruction(Instruction::Exit)),
-                    _ => {}
+        let _ = rl.add_history_entry(code.trim_end());
+
+        match stackpp.compile(code.to_string()) {
+            Ok(program) => {
+                println!("AST    : {program:?}");
+                match stackpp.eval(program) {
+                    Err(Error::Exit) => break 'repl,
+                    Err(e) => println!("Error  : {e}"),
+                    Ok(()) => {}
                 }
             }
+            Err(e) => println!("Error  : {e}"),
         }
-        result
+        println!("Result : {stackpp:?}");
+    }
+    if let Some(path) = &history {
+        let _ = rl.save_history(path);
     }
+}
 
-    fn eval(&mut self, program: Vec<Type>) {
-        for order in program {
-            match order {
-                Type::Instruction(instruction) => match instruction {
-                    Instruction::Add => {
-                        let b = self.pop().get_number();
-                        let a = self.pop().get_number();
-                        self.stack.push(Type::Number(a + b))
-                    }
-                    Instruction::Sub => {
-                        let b = self.pop().get_number();
-                        let a = self.pop().get_number();
-                        self.stack.push(Type::Number(a - b))
-                    }
-                    Instruction::Mul => {
-                        let b = self.pop().get_number();
-                        let a = self.pop().get_number();
-                        self.stack.push(Type::Number(a * b))
-                    }
-                    Instruction::Div => {
-                        let b = self.pop().get_number();
-                        let a = self.pop().get_number();
-                        self.stack.push(Type::Number(a / b))
-                    }
-                    Instruction::Mod => {
-                        let b = self.pop().get_number();
-                        let a = self.pop().get_number();
-                        self.stack.push(Type::Number(a % b))
-                    }
-                    Instruction::Pow => {
-                        let b = self.pop().get_number();
-                        let a = self.pop().get_number();
-                        self.stack.push(Type::Number(a.powf(b)))
-                    }
-                    Instruction::Concat => {
-                        let b = self.pop().get_string();
-                        let a = self.pop().get_string();
-                        self.stack.push(Type::String(a + &b));
-                    }
-                    Instruction::Print => {
-                        let a = self.pop().get_string();
-                        print!("{}", a);
-                    }
-                    Instruction::Input => self.stack.push(Type::String(
-                        DefaultEditor::new().unwrap().readline("").unwrap(),
-                    )),
-                    Instruction::Equal => {
-                        let b = self.pop().get_string();
-                        let a = self.pop().get_string();
-                        self.stack.push(Type::Bool(a == b));
-                    }
-                    Instruction::LessThan => {
-                        let b = self.pop().get_number();
-                        let a = self.pop().get_number();
-                        self.stack.push(Type::Bool(a < b))
-                    }
-                    Instruction::GreaterThan => {
-                        let b = self.pop().get_number();
-                        let a = self.pop().get_number();
-                        self.stack.push(Type::Bool(a > b))
-                    }
-                    Instruction::Eval => {
-                        let code = self.pop().get_block();
-                        self.eval(code);
-                    }
-                    Instruction::When => {
-                        let code = self.pop().get_block();
-                        let condition = self.pop().get_bool();
-                        if condition {
-                            self.eval(code);
-                        };
-                    }
-                    Instruction::IfElse => {
-                        let code_false = self.pop().get_block();
-                        let code_true = self.pop().get_block();
-                        let condition = self.pop().get_bool();
-                        if condition {
-                            self.eval(code_true);
-                        } else {
-                            self.eval(code_false);
-                        };
-                    }
-                    Instruction::While => {
-                        let code = self.pop().get_block();
-                        let condition = self.pop().get_block();
-                        while {
-                            self.eval(condition.clone());
-                            self.pop().get_bool()
-                        } {
-                            self.eval(code.clone());
-                        }
-                    }
-                    Instruction::Until => {
-                        let code = self.pop().get_block();
-                        let condition = self.pop().get_block();
-                        while {
-                            self.eval(condition.clone());
-                            !self.pop().get_bool()
-                        } {
-                            self.eval(code.clone());
-                        }
-                    }
-                    Instruction::Let => {
-                        let name = self.pop().get_string();
-                        let value = self.pop();
-                        self.memory.insert(name, value);
-                    }
-                    Instruction::Swap => {
-                        let b = self.pop();
-                        let a = self.pop();
-                        self.stack.push(b);
-                        self.stack.push(a);
-                    }
-                    Instruction::Copy => {
-                        let a = self.pop();
-                        self.stack.push(a.clone());
-                        self.stack.push(a);
-                    }
-                    Instruction::Pop => {
-                        self.stack.pop();
-                    }
-                    Instruction::Insert => {
-                        let new = self.pop();
-                        let index = self.pop().get_number() as usize;
-                        let mut block = self.pop().get_block();
-                        block.insert(index, new);
-                        self.stack.push(Type::Block(block));
-                    }
-                    Instruction::Change => {
-                        let new = self.pop();
-                        let index = self.pop().get_number() as usize;
-                        let mut block = self.pop().get_block();
-                        block[index] = new;
-                        self.stack.push(Type::Block(block));
-                    }
-                    Instruction::Delete => {
-                        let index = self.pop().get_number() as usize;
-                        let mut block = self.pop().get_block();
-                        block.remove(index);
-                        self.stack.push(Type::Block(block));
-                    }
-                    Instruction::Append => {
-                        let new = self.pop();
-                        let mut block = self.pop().get_block();
-                        block.push(new);
-                        self.stack.push(Type::Block(block));
-                    }
-                    Instruction::Exit => exit(0),
-                },
-                Type::Variable(name) => {
-                    if let Some(value) = self.memory.get(&name) {
-                        self.stack.push(value.to_owned());
-                    } else {
-                        self.stack.push(Type::Variable(name));
-                    }
-                }
-                other => self.stack.push(other),
-            }
-        }
+fn banner(startup: Option<&str>, variables: usize) -> String {
+    let mut banner = format!("Stack++ v{VERSION}");
+    if let Some(file) = startup {
+        banner += &format!("\nLoaded {file} ({variables} variables defined)");
     }
+    banner
+}
 
-    fn pop(&mut self) -> Type {
-        if let Some(value) = self.stack.pop() {
-            value
-        } else {
-            Type::Error(Error::StackEmpty)
+fn run_or_exit(stackpp: &mut Core, code: String) {
+    match stackpp
+        .compile(code)
+        .map_err(Error::Parse)
+        .and_then(|program| stackpp.eval(program))
+    {
+        Err(Error::Exit) => exit(0),
+        Err(e) => {
+            eprintln!("Error! {e}");
+            exit(1);
         }
+        Ok(()) => {}
     }
 }
@@ -0,0 +1,239 @@
+use std::{
+    fs::{create_dir_all, read_to_string, remove_dir_all, write},
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+fn stackpp(args: &[&str]) -> Output {
+    stackpp_with_input(args, "")
+}
+
+/// Runs the binary with the given text piped into its stdin.
+fn stackpp_with_input(args: &[&str], input: &str) -> Output {
+    run(
+        Command::new(env!("CARGO_BIN_EXE_stackpp")).args(args),
+        input,
+    )
+}
+
+/// Starts the REPL with `home` as the home directory, feeding it `input`.
+fn repl(home: &Path, input: &str) -> Output {
+    run(
+        Command::new(env!("CARGO_BIN_EXE_stackpp")).env("HOME", home),
+        input,
+    )
+}
+
+fn run(command: &mut Command, input: &str) -> Output {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// A fresh directory under the system temp dir for one test.
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("stackpp-{name}"));
+    let _ = remove_dir_all(&dir);
+    create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn warn_leaks_reports_a_growing_loop() {
+    let code = "0 { dup 3 less-than } { dup 1 add } while";
+    let output = stackpp(&["--warn-leaks", "-e", code]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Warning! loop iteration grew the stack from 1 to 2 items"));
+
+    let output = stackpp(&["-e", code]);
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn read_number_parses_piped_input() {
+    let output = stackpp_with_input(&["-e", "read-number"], "42\n");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("stack: [Int(42)]"));
+
+    let output = stackpp_with_input(&["-e", "read-number"], "2.5\n");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("stack: [Number(2.5)]"));
+
+    let output = stackpp_with_input(&["-e", "read-number"], "abc\n");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Error! InvalidNumber"));
+}
+
+#[test]
+fn prompt_shows_the_label_and_reads_a_line() {
+    let output = stackpp_with_input(&["-e", r#""Name? " prompt"#], "Ada\n");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("Name? "));
+    assert!(stdout.contains(r#"stack: [String("Ada")]"#));
+}
+
+#[test]
+fn assert_empty_lists_leftover_items() {
+    let output = stackpp(&["-e", r#"1 "two" assert-empty"#]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(r#"Error! stack is not empty: 1 "two""#));
+
+    let output = stackpp(&["-e", "1 2 add pop assert-empty"]);
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn exit_stops_with_a_success_status() {
+    let output = stackpp(&["-e", r#""before" println exit "after" println"#]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "before\n");
+}
+
+#[test]
+fn errors_exit_with_a_failure_status() {
+    let path = std::env::temp_dir().join("stackpp-underflow-test.spp");
+    std::fs::write(&path, r#"1 add "after" print"#).unwrap();
+    let output = stackpp(&[path.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Error! StackEmpty"));
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("after"));
+}
+
+#[test]
+fn print_decodes_new_lines() {
+    let output = stackpp(&["-e", r#""a\nb" print"#]);
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("a\nb"));
+}
+
+#[test]
+fn ring_stack_drops_the_oldest_items() {
+    let output = stackpp(&["--ring-stack", "2", "-e", "1 2 3 4"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("stack: [Int(3), Int(4)]"));
+}
+
+#[test]
+fn tee_prints_the_source_form_to_stderr() {
+    let output = stackpp(&["-e", r#"{ 1 2 } list tee "hi" tee"#]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(stderr, "{ 1 2 } list\n\"hi\"\n");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(r#"stack: [List([Int(1), Int(2)]), String("hi")]"#));
+}
+
+#[test]
+fn eval_flag_runs_a_one_liner() {
+    let output = stackpp(&["-e", "2 3 add print"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("5Result : Core { stack: [], memory: {"));
+    assert!(!stdout.contains("frozen"));
+
+    let output = stackpp(&["--eval", "1", "file.spp"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("cannot be used with"));
+}
+
+#[test]
+fn nil_prints_nothing() {
+    let output = stackpp(&["-e", "nil print"]);
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("Result : "));
+
+    let output = stackpp(&["--undefined-as-nil", "-e", "$missing"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("stack: [Nil]"));
+}
+
+#[test]
+fn println_ends_with_a_new_line() {
+    let output = stackpp(&["-e", r#""a" println "b" print"#]);
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("a\nbResult : "));
+}
+
+#[test]
+fn banner_shows_the_version_and_startup_file() {
+    let home = temp_dir("banner");
+    let output = repl(&home, "");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with(&format!("Stack++ v{}\n", env!("CARGO_PKG_VERSION"))));
+    assert!(!stdout.contains("Loaded"));
+
+    let rc = home.join(".stackpprc");
+    write(&rc, r#"1 "a" let { 2 } "two" def"#).unwrap();
+    let output = repl(&home, "");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(&format!("Loaded {} (2 variables defined)", rc.display())));
+}
+
+#[test]
+fn rc_file_definitions_reach_the_repl() {
+    let home = temp_dir("rc");
+    write(home.join(".stackpprc"), r#"1 "a" let { 2 } "two" def"#).unwrap();
+    let output = repl(&home, "two $a add\n\n");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("stack: [Int(3)]"));
+
+    write(home.join(".stackpprc"), "{ 1 2").unwrap();
+    let output = repl(&home, "");
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("failed: unterminated block at line 1, column 1"));
+}
+
+#[test]
+fn include_dirs_resolve_imports() {
+    let dir = temp_dir("include");
+    write(dir.join("helpers.spp"), r#"{ 2 mul } "double" def"#).unwrap();
+    let code = r#""helpers.spp" import 21 double"#;
+
+    let output = stackpp(&["-I", dir.to_str().unwrap(), "-e", code]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("stack: [Int(42)]"));
+
+    let output = stackpp(&["-e", code]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn end_of_input_is_handled() {
+    // Ctrl-D at the prompt leaves the REPL normally
+    let output = repl(&temp_dir("eof"), "1 2 add\n");
+    assert!(output.status.success());
+
+    // In a script, input at end of stream is an error rather than a crash
+    let output = stackpp(&["-e", "input"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Error! EndOfInput"));
+}
+
+#[test]
+fn repl_history_persists_between_sessions() {
+    let home = temp_dir("history");
+    let output = repl(&home, "1 2 add\n\n");
+    assert!(output.status.success());
+    let history = read_to_string(home.join(".stackpp_history")).unwrap();
+    assert!(history.contains("1 2 add"));
+
+    repl(&home, "3 4 mul\n\n");
+    let history = read_to_string(home.join(".stackpp_history")).unwrap();
+    assert!(history.contains("1 2 add") && history.contains("3 4 mul"));
+}
+
+#[test]
+fn script_errors_show_the_line() {
+    let path = temp_dir("line").join("script.spp");
+    write(&path, "1\n{\n  pop pop\n} eval\n").unwrap();
+    let output = stackpp(&[path.to_str().unwrap()]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Error! StackEmpty at line 3, column 7"));
+}
@@ -0,0 +1,25 @@
+use stackpp::{Core, Error, Type};
+
+#[test]
+fn runs_a_program_through_the_public_api() {
+    let mut core = Core::new();
+    let program = Core::parse("2 3 add \"x\" let $x 4 mul".to_string()).unwrap();
+    core.eval(program).unwrap();
+    assert!(matches!(core.stack[..], [Type::Int(20)]));
+    assert!(matches!(core.memory.get("x"), Some(Type::Int(5))));
+}
+
+#[test]
+fn reports_errors_through_the_public_api() {
+    let mut core = Core::new();
+    let program = Core::parse("pop".to_string()).unwrap();
+    assert!(matches!(core.eval(program), Err(Error::At(..))));
+}
+
+#[test]
+fn exit_returns_control_to_the_embedder() {
+    let mut core = Core::new();
+    let program = Core::parse("1 exit 2".to_string()).unwrap();
+    assert!(matches!(core.eval(program), Err(Error::Exit)));
+    assert!(matches!(core.stack[..], [Type::Int(1)]));
+}